@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simple8b::simple8b::{decode_all, decode_all_into, encode_all, WordsDecoder};
+
+const LEN: usize = 1_000_000;
+
+// Small deterministic PRNG so runs are comparable without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Values whose width is drawn from `widths` afresh every 64 values, so every
+// packed selector in range shows up.
+fn dataset(seed: u64, widths: std::ops::Range<u32>) -> Vec<u64> {
+    let mut rng = XorShift(seed);
+    let span = u64::from(widths.end - widths.start);
+    let mut width = widths.start;
+    (0..LEN)
+        .map(|i| {
+            if i % 64 == 0 {
+                width = widths.start + (rng.next() % span) as u32;
+            }
+            rng.next() >> (64 - width.max(1))
+        })
+        .collect()
+}
+
+fn datasets() -> Vec<(&'static str, Vec<u64>)> {
+    vec![
+        ("narrow", dataset(1, 1..9)),
+        ("mixed", dataset(2, 1..61)),
+        ("wide", dataset(3, 15..31)),
+    ]
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (name, src) in datasets() {
+        let encoded = encode_all(&src).unwrap();
+        let mut dst = vec![0u64; src.len()];
+        group.throughput(Throughput::Elements(src.len() as u64));
+        group.bench_with_input(BenchmarkId::new("decode_all", name), &encoded, |b, w| {
+            b.iter(|| decode_all::<u64>(black_box(w)).unwrap())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("decode_all_into", name),
+            &encoded,
+            |b, w| b.iter(|| decode_all_into(black_box(w), &mut dst).unwrap()),
+        );
+        group.bench_with_input(BenchmarkId::new("words", name), &encoded, |b, w| {
+            b.iter(|| {
+                let mut decoder = WordsDecoder::new(black_box(w));
+                let mut buf = [0u64; 4096];
+                let mut total = 0;
+                while let Ok(n @ 1..) = decoder.decode_into(&mut buf) {
+                    total += n;
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
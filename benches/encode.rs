@@ -0,0 +1,54 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simple8b::simple8b::encode_all;
+
+const LEN: usize = 1_000_000;
+
+// Small deterministic PRNG so runs are comparable without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Values whose width is drawn from `widths` afresh every 64 values, so every
+// packed selector in range shows up.
+fn dataset(seed: u64, widths: std::ops::Range<u32>) -> Vec<u64> {
+    let mut rng = XorShift(seed);
+    let span = u64::from(widths.end - widths.start);
+    let mut width = widths.start;
+    (0..LEN)
+        .map(|i| {
+            if i % 64 == 0 {
+                width = widths.start + (rng.next() % span) as u32;
+            }
+            rng.next() >> (64 - width.max(1))
+        })
+        .collect()
+}
+
+fn datasets() -> Vec<(&'static str, Vec<u64>)> {
+    vec![
+        ("narrow", dataset(1, 1..9)),
+        ("mixed", dataset(2, 1..61)),
+        ("wide", dataset(3, 15..31)),
+    ]
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (name, src) in datasets() {
+        group.throughput(Throughput::Elements(src.len() as u64));
+        group.bench_with_input(BenchmarkId::new("encode_all", name), &src, |b, s| {
+            b.iter(|| encode_all(black_box(s)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simple8b::simple8b::{decode_all, EncodeStrategy, Options};
+
+const LEN: usize = 100_000;
+
+// Small deterministic PRNG so runs are comparable without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Zigzag deltas of a slowly drifting sensor with the occasional spike.
+fn sensor() -> Vec<u64> {
+    let mut rng = XorShift(0x9e3779b97f4a7c15);
+    (0..LEN)
+        .map(|_| {
+            let r = rng.next();
+            if r.is_multiple_of(100) {
+                r >> 40
+            } else {
+                r % 8
+            }
+        })
+        .collect()
+}
+
+// Delta-of-delta of timestamps with jitter on a few points.
+fn timestamps() -> Vec<u64> {
+    let mut rng = XorShift(0xdeadbeef);
+    (0..LEN)
+        .map(|_| {
+            let r = rng.next();
+            if r.is_multiple_of(16) {
+                r % 2000
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+// Counters whose width changes every few hundred points.
+fn mixed() -> Vec<u64> {
+    let mut rng = XorShift(42);
+    (0..LEN)
+        .map(|i| rng.next() >> (40 + (i / 300) % 24))
+        .collect()
+}
+
+fn datasets() -> Vec<(&'static str, Vec<u64>)> {
+    vec![
+        ("sensor", sensor()),
+        ("timestamps", timestamps()),
+        ("mixed", mixed()),
+    ]
+}
+
+fn options(strategy: EncodeStrategy) -> Options {
+    Options {
+        strategy,
+        ..Options::default()
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (name, src) in datasets() {
+        let greedy = options(EncodeStrategy::Greedy).encode_all(&src).unwrap();
+        let optimal = options(EncodeStrategy::Optimal).encode_all(&src).unwrap();
+        println!(
+            "{}: {} values, greedy {} words, optimal {} words ({:.2}% smaller)",
+            name,
+            src.len(),
+            greedy.len(),
+            optimal.len(),
+            100.0 * (greedy.len() - optimal.len()) as f64 / greedy.len() as f64
+        );
+
+        group.throughput(Throughput::Elements(src.len() as u64));
+        for strategy in [EncodeStrategy::Greedy, EncodeStrategy::Optimal] {
+            let opts = options(strategy);
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", strategy), name),
+                &src,
+                |b, src| b.iter(|| opts.encode_all(black_box(src)).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (name, src) in datasets() {
+        group.throughput(Throughput::Elements(src.len() as u64));
+        for strategy in [EncodeStrategy::Greedy, EncodeStrategy::Optimal] {
+            let encoded = options(strategy).encode_all(&src).unwrap();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", strategy), name),
+                &encoded,
+                |b, encoded| b.iter(|| decode_all::<u64>(black_box(encoded)).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode);
+criterion_main!(benches);
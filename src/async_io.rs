@@ -0,0 +1,246 @@
+//! Async counterparts of the `io` adapters over tokio's `AsyncRead` and
+//! `AsyncWrite`. Enabled by the `tokio` feature.
+
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::io::Unpacker;
+use crate::simple8b::{Encoder, Error, Options};
+
+/// Async `EncoderWriter`: writes packed words to `W` as soon as they are
+/// final.
+///
+/// The futures are not cancel safe; dropping one mid-write may leave part of
+/// a word on `W`.
+pub struct AsyncEncoderWriter<W: AsyncWrite + Unpin> {
+    inner: W,
+    encoder: Encoder,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncoderWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, Options::default())
+    }
+
+    pub fn with_options(inner: W, opts: Options) -> Self {
+        AsyncEncoderWriter {
+            inner,
+            encoder: Encoder::with_options(opts),
+        }
+    }
+
+    /// Buffers `v` and writes out any words it completes.
+    pub async fn write(&mut self, v: u64) -> io::Result<()> {
+        self.encoder.write(v)?;
+        self.drain().await
+    }
+
+    /// Writes every value of `src`.
+    pub async fn write_all(&mut self, src: &[u64]) -> io::Result<()> {
+        for &v in src {
+            self.encoder.write(v)?;
+        }
+        self.drain().await
+    }
+
+    /// Packs the buffered values, flushes the writer and returns it.
+    pub async fn finish(mut self) -> io::Result<W> {
+        self.encoder.flush_all();
+        self.drain().await?;
+        self.inner.flush().await?;
+        Ok(self.inner)
+    }
+
+    /// Returns the writer, discarding any values not yet packed.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    async fn drain(&mut self) -> io::Result<()> {
+        if !self.encoder.bytes().is_empty() {
+            self.inner.write_all(self.encoder.bytes()).await?;
+            self.encoder.clear_bytes();
+        }
+        Ok(())
+    }
+}
+
+/// Async `DecoderReader`: a `Stream` of the values packed in `R`.
+///
+/// Yields one `Result` per value and ends after the first error. Short reads
+/// are stitched back into whole words.
+pub struct AsyncDecoderReader<R: AsyncRead + Unpin> {
+    inner: R,
+    unpacker: Unpacker,
+    word: [u8; 8],
+    filled: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecoderReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_options(inner, Options::default())
+    }
+
+    pub fn with_options(inner: R, opts: Options) -> Self {
+        AsyncDecoderReader {
+            inner,
+            unpacker: Unpacker::new(opts),
+            word: [0; 8],
+            filled: 0,
+            done: false,
+        }
+    }
+
+    /// Stops after exactly `count` values, like `Decoder::limit`.
+    ///
+    /// Nothing past them is read, and input that ends before them fails with
+    /// `CountMismatch`.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.unpacker.set_limit(count);
+        self
+    }
+
+    /// Returns the next value, like `StreamExt::next`.
+    pub async fn next(&mut self) -> Option<io::Result<u64>> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    fn poll_value(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<u64>>> {
+        loop {
+            if let Some(v) = self.unpacker.pop() {
+                return Poll::Ready(Ok(Some(v)));
+            }
+            if self.unpacker.is_full() {
+                return Poll::Ready(Ok(None));
+            }
+
+            let mut buf = ReadBuf::new(&mut self.word[self.filled..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+            let n = buf.filled().len();
+            if n == 0 {
+                if self.filled > 0 {
+                    let remaining = self.filled;
+                    return Poll::Ready(Err(Error::TruncatedInput { remaining }.into()));
+                }
+                self.unpacker.end()?;
+                return Poll::Ready(Ok(None));
+            }
+
+            self.filled += n;
+            if self.filled == self.word.len() {
+                self.filled = 0;
+                self.unpacker.push(self.word)?;
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncDecoderReader<R> {
+    type Item = io::Result<u64>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let r = ready!(this.poll_value(cx));
+        this.done = !matches!(r, Ok(Some(_)));
+        Poll::Ready(r.transpose())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple8b;
+
+    // Hands out at most three bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let n = buf.remaining().min(self.0.len()).min(3);
+            buf.put_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_round_trip() {
+        let src: Vec<u64> = crate::sample(10_000);
+        let mut writer = AsyncEncoderWriter::new(Vec::new());
+        for &v in &src[..5000] {
+            writer.write(v).await.unwrap();
+        }
+        writer.write_all(&src[5000..]).await.unwrap();
+        let bytes = writer.finish().await.unwrap();
+        let mut encoder = simple8b::Encoder::new();
+        encoder.set_values(&src).unwrap();
+        assert_eq!(bytes, encoder.finish());
+
+        let mut reader = AsyncDecoderReader::new(Trickle(&bytes));
+        let mut decoded = Vec::new();
+        while let Some(v) = reader.next().await {
+            decoded.push(v.unwrap());
+        }
+        assert_eq!(decoded, src);
+    }
+
+    #[tokio::test]
+    async fn test_async_partial_word() {
+        let mut bytes = (15u64 << 60 | 42).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let mut reader = AsyncDecoderReader::new(Trickle(&bytes));
+        assert_eq!(reader.next().await.unwrap().unwrap(), 42);
+
+        let err = reader.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.into_inner().unwrap().downcast_ref::<Error>(),
+            Some(&Error::TruncatedInput { remaining: 3 })
+        );
+        assert!(reader.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_limit() {
+        let bytes = (7u64 << 60).to_be_bytes();
+        let mut reader = AsyncDecoderReader::new(Trickle(&bytes)).limit(4);
+        let mut decoded = Vec::new();
+        while let Some(v) = reader.next().await {
+            decoded.push(v.unwrap());
+        }
+        assert_eq!(decoded, [0; 4]);
+    }
+}
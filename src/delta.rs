@@ -0,0 +1,225 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::simple8b::{Decoder, Encoder, Error, Options};
+
+/// Maps signed integers onto unsigned ones so small magnitudes stay small.
+pub fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+/// How many times successive values are differenced before packing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Packs `v[i] - v[i-1]`; suits counters.
+    #[default]
+    Delta,
+    /// Packs the difference of successive deltas; suits regular timestamps.
+    DeltaOfDelta,
+}
+
+/// Encoder for `i64` series.
+///
+/// The first value is stored as a raw big-endian word, followed by the
+/// simple8b-packed zigzag deltas of the rest. Arithmetic wraps, so any `i64`
+/// sequence round-trips as long as its zigzag deltas fit in `MAX_VALUE`.
+#[derive(Clone)]
+pub struct DeltaEncoder {
+    inner: Encoder,
+    order: Order,
+    first: Option<i64>,
+    prev: i64,
+    prev_delta: i64,
+}
+
+impl DeltaEncoder {
+    pub fn new(order: Order) -> Self {
+        Self::with_options(order, Options::default())
+    }
+
+    /// Packs the deltas with `opts`; a table with zero runs suits
+    /// `DeltaOfDelta`, whose regular stretches difference to zero.
+    pub fn with_options(order: Order, opts: Options) -> Self {
+        DeltaEncoder {
+            inner: Encoder::with_options(opts),
+            order,
+            first: None,
+            prev: 0,
+            prev_delta: 0,
+        }
+    }
+
+    pub fn write(&mut self, v: i64) -> Result<(), Error> {
+        if self.first.is_none() {
+            self.first = Some(v);
+            self.prev = v;
+            return Ok(());
+        }
+
+        let delta = v.wrapping_sub(self.prev);
+        let packed = match self.order {
+            Order::Delta => delta,
+            Order::DeltaOfDelta => delta.wrapping_sub(self.prev_delta),
+        };
+        // The first value is not part of the packed stream.
+        self.inner
+            .write(zigzag_encode(packed))
+            .map_err(|e| e.at(1))?;
+        self.prev = v;
+        self.prev_delta = delta;
+        Ok(())
+    }
+
+    /// Packs all buffered values and returns the encoded bytes.
+    pub fn finish(self) -> Vec<u8> {
+        let first = match self.first {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let words = self.inner.finish();
+        let mut bytes = vec![0; 8 + words.len()];
+        BigEndian::write_i64(&mut bytes[..8], first);
+        bytes[8..].copy_from_slice(&words);
+        bytes
+    }
+}
+
+/// Decoder for the output of `DeltaEncoder`.
+///
+/// The `order` and options must match the ones used to encode.
+#[derive(Clone)]
+pub struct DeltaDecoder<'a> {
+    bytes: &'a [u8],
+    inner: Decoder<'a>,
+    order: Order,
+    started: bool,
+    prev: i64,
+    prev_delta: i64,
+}
+
+impl<'a> DeltaDecoder<'a> {
+    pub fn new(bytes: &'a [u8], order: Order) -> Self {
+        Self::with_options(bytes, order, Options::default())
+    }
+
+    pub fn with_options(bytes: &'a [u8], order: Order, opts: Options) -> Self {
+        DeltaDecoder {
+            bytes,
+            inner: Decoder::with_options(&[], opts),
+            order,
+            started: false,
+            prev: 0,
+            prev_delta: 0,
+        }
+    }
+}
+
+impl Iterator for DeltaDecoder<'_> {
+    type Item = Result<i64, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            if self.bytes.is_empty() {
+                return None;
+            }
+            if self.bytes.len() < 8 {
+                return Some(Err(Error::TruncatedInput {
+                    remaining: self.bytes.len(),
+                }));
+            }
+            self.prev = BigEndian::read_i64(&self.bytes[..8]);
+            self.inner.set_bytes(&self.bytes[8..]);
+            return Some(Ok(self.prev));
+        }
+
+        let packed = match self.inner.next()? {
+            Ok(v) => zigzag_decode(v),
+            Err(e) => return Some(Err(e)),
+        };
+        let delta = match self.order {
+            Order::Delta => packed,
+            Order::DeltaOfDelta => packed.wrapping_add(self.prev_delta),
+        };
+        self.prev = self.prev.wrapping_add(delta);
+        self.prev_delta = delta;
+        Some(Ok(self.prev))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple8b::SelectorTable;
+
+    fn round_trip(src: &[i64], order: Order) -> Vec<u8> {
+        round_trip_with(src, order, Options::default())
+    }
+
+    fn round_trip_with(src: &[i64], order: Order, opts: Options) -> Vec<u8> {
+        let mut encoder = DeltaEncoder::with_options(order, opts);
+        for &v in src {
+            encoder.write(v).unwrap();
+        }
+        let bytes = encoder.finish();
+        let decoded: Result<Vec<i64>, Error> =
+            DeltaDecoder::with_options(&bytes, order, opts).collect();
+        assert_eq!(decoded.unwrap(), src);
+        bytes
+    }
+
+    #[test]
+    fn test_zigzag() {
+        for v in [0, -1, 1, -2, 2, i64::MIN, i64::MAX] {
+            assert_eq!(zigzag_decode(zigzag_encode(v)), v);
+        }
+        assert_eq!(zigzag_encode(-1), 1);
+        assert_eq!(zigzag_encode(1), 2);
+    }
+
+    #[test]
+    fn test_signed_counter() {
+        let src: Vec<i64> = (0..500).map(|i| 1000 - i * 3 + (i % 5)).collect();
+        round_trip(&src, Order::Delta);
+        round_trip(&[i64::MIN, i64::MAX, i64::MIN], Order::Delta);
+        round_trip(&[], Order::Delta);
+    }
+
+    #[test]
+    fn test_regular_timestamps() {
+        let start = 1_700_000_000_000_000_000i64;
+        let src: Vec<i64> = (0..1000).map(|i| start + i * 10_000_000_000).collect();
+        let bytes = round_trip(&src, Order::DeltaOfDelta);
+        assert!(bytes.len() < src.len());
+
+        // The zeros after the first delta pack into run words.
+        let opts = Options {
+            table: SelectorTable::ZERO_RUNS,
+            ..Options::default()
+        };
+        let runs = round_trip_with(&src, Order::DeltaOfDelta, opts);
+        assert!(
+            runs.len() < bytes.len(),
+            "{} vs {}",
+            runs.len(),
+            bytes.len()
+        );
+    }
+
+    #[test]
+    fn test_delta_too_large() {
+        let mut encoder = DeltaEncoder::new(Order::Delta);
+        encoder.write(0).unwrap();
+        encoder.write(1).unwrap();
+        assert_eq!(
+            encoder.write(i64::MAX),
+            Err(Error::ValueTooLarge {
+                index: 2,
+                value: zigzag_encode(i64::MAX - 1),
+            })
+        );
+    }
+}
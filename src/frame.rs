@@ -0,0 +1,366 @@
+//! Self-describing frames around a packed word stream.
+//!
+//! A frame is laid out as follows, with multi-byte header fields big-endian:
+//!
+//! | bytes | field                                              |
+//! |-------|----------------------------------------------------|
+//! | 4     | magic, `S8BF`                                      |
+//! | 1     | format version, currently 1                        |
+//! | 1     | option flags, see below                            |
+//! | 1     | selector table id                                  |
+//! | 8     | number of values                                   |
+//! | 8·k   | packed words, in the byte order the flags name     |
+//! | 4     | CRC-32C of everything before it                    |
+//!
+//! Flag bits: 0 `run_values`, 1 `escape`, 2 little-endian words, 3 `tail`.
+//! Other bits must be zero.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::simple8b::{Decoder, Encoder, Endian, Error, Options, SelectorTable};
+
+const MAGIC: [u8; 4] = *b"S8BF";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 15;
+const TRAILER_LEN: usize = 4;
+
+const RUN_VALUES: u8 = 1 << 0;
+const ESCAPE: u8 = 1 << 1;
+const LITTLE_ENDIAN: u8 = 1 << 2;
+const TAIL: u8 = 1 << 3;
+
+/// Encoder that wraps its output in a frame.
+#[derive(Clone, Default)]
+pub struct FrameEncoder {
+    inner: Encoder,
+    opts: Options,
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(opts: Options) -> Self {
+        FrameEncoder {
+            inner: Encoder::with_options(opts),
+            opts,
+        }
+    }
+
+    pub fn write(&mut self, v: u64) -> Result<(), Error> {
+        self.inner.write(v)
+    }
+
+    /// Packs all buffered values and returns the finished frame.
+    pub fn finish(self) -> Vec<u8> {
+        let count = self.inner.len() as u64;
+        let words = self.inner.finish();
+        let mut b = Vec::with_capacity(HEADER_LEN + words.len() + TRAILER_LEN);
+        b.extend_from_slice(&MAGIC);
+        b.push(VERSION);
+        b.push(flags(&self.opts));
+        b.push(self.opts.table.id());
+        b.extend_from_slice(&count.to_be_bytes());
+        b.extend_from_slice(&words);
+        let crc = crc32c(&b);
+        b.extend_from_slice(&crc.to_be_bytes());
+        b
+    }
+}
+
+/// Decoder for a frame written by `FrameEncoder`.
+///
+/// The header and checksum are validated up front, so a frame that opens
+/// cleanly was stored intact. Yields exactly the number of values recorded
+/// in the header.
+#[derive(Clone)]
+pub struct FrameDecoder<'a> {
+    inner: Decoder<'a>,
+    opts: Options,
+    count: u64,
+}
+
+impl<'a> FrameDecoder<'a> {
+    /// Opens a frame packed with one of the preset selector tables.
+    pub fn new(b: &'a [u8]) -> Result<Self, Error> {
+        Self::with_tables(b, &[])
+    }
+
+    /// Opens a frame packed with a preset or with one of `tables`, matched
+    /// by id. Fails with `UnknownTable` if none of them has the frame's id.
+    pub fn with_tables(b: &'a [u8], tables: &[SelectorTable]) -> Result<Self, Error> {
+        if b.len() < HEADER_LEN + TRAILER_LEN {
+            return Err(Error::TruncatedInput { remaining: b.len() });
+        }
+        if b[..4] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        if b[4] != VERSION {
+            return Err(Error::UnsupportedVersion(b[4]));
+        }
+        let (body, trailer) = b.split_at(b.len() - TRAILER_LEN);
+        if crc32c(body) != BigEndian::read_u32(trailer) {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        let id = b[6];
+        let table = SelectorTable::preset(id)
+            .or_else(|| tables.iter().copied().find(|t| t.id() == id))
+            .ok_or(Error::UnknownTable(id))?;
+        let opts = Options {
+            table,
+            ..options(b[5])?
+        };
+        let count = BigEndian::read_u64(&b[7..HEADER_LEN]);
+        let body = &body[HEADER_LEN..];
+        // A count past usize::MAX cannot be met, so it still ends in
+        // `CountMismatch`.
+        let limit = usize::try_from(count).unwrap_or(usize::MAX);
+        Ok(FrameDecoder {
+            inner: Decoder::with_options(body, opts).limit(limit),
+            opts,
+            count,
+        })
+    }
+
+    /// Returns the number of values in the frame.
+    pub fn value_count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the options the frame was encoded with.
+    pub fn options(&self) -> Options {
+        self.opts
+    }
+}
+
+impl Iterator for FrameDecoder<'_> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+fn flags(opts: &Options) -> u8 {
+    let mut f = 0;
+    if opts.run_values {
+        f |= RUN_VALUES;
+    }
+    if opts.escape {
+        f |= ESCAPE;
+    }
+    if opts.endian == Endian::Little {
+        f |= LITTLE_ENDIAN;
+    }
+    if opts.tail {
+        f |= TAIL;
+    }
+    f
+}
+
+fn options(f: u8) -> Result<Options, Error> {
+    let unknown = f & !(RUN_VALUES | ESCAPE | LITTLE_ENDIAN | TAIL);
+    if unknown != 0 {
+        return Err(Error::InvalidFlags(unknown));
+    }
+    Ok(Options {
+        run_values: f & RUN_VALUES != 0,
+        escape: f & ESCAPE != 0,
+        tail: f & TAIL != 0,
+        endian: if f & LITTLE_ENDIAN != 0 {
+            Endian::Little
+        } else {
+            Endian::Big
+        },
+        ..Options::default()
+    })
+}
+
+// CRC-32C (Castagnoli), as used by iSCSI, ext4 and Snappy framing.
+fn crc32c(b: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    (c >> 1) ^ 0x82f6_3b78
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    !b.iter().fold(!0u32, |c, &x| {
+        TABLE[((c ^ u32::from(x)) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(src: &[u64], opts: Options) -> Vec<u8> {
+        let mut encoder = FrameEncoder::with_options(opts);
+        for &v in src {
+            encoder.write(v).unwrap();
+        }
+        encoder.finish()
+    }
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let src: Vec<u64> = crate::sample(1000);
+        let opts = Options {
+            escape: true,
+            endian: Endian::Little,
+            ..Options::default()
+        };
+        let b = frame(&src, opts);
+        assert_eq!(&b[..7], b"S8BF\x01\x06\x00");
+
+        let decoder = FrameDecoder::new(&b).unwrap();
+        assert_eq!(decoder.value_count(), 1000);
+        assert_eq!(decoder.options(), opts);
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        let empty = frame(&[], Options::default());
+        assert_eq!(empty.len(), HEADER_LEN + TRAILER_LEN);
+        assert_eq!(FrameDecoder::new(&empty).unwrap().next(), None);
+    }
+
+    #[test]
+    fn test_frame_tail() {
+        let opts = Options {
+            tail: true,
+            ..Options::default()
+        };
+        let src: Vec<u64> = (0..249).map(|i| i % 4).collect();
+        let b = frame(&src, opts);
+        // 240 two-bit values in eight words, then the last nine in one.
+        assert_eq!(b.len(), HEADER_LEN + 9 * 8 + TRAILER_LEN);
+
+        let decoder = FrameDecoder::new(&b).unwrap();
+        assert_eq!(decoder.options(), opts);
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src);
+    }
+
+    #[test]
+    fn test_frame_tables() {
+        let mut entries = [(1, 60); 16];
+        entries[0] = (64, 0);
+        entries[2] = (12, 5);
+        let table = SelectorTable::new(200, entries, 7).unwrap();
+        let opts = Options {
+            table,
+            ..Options::default()
+        };
+        let src: Vec<u64> = (0..100).map(|i| if i < 70 { 7 } else { i % 32 }).collect();
+        let b = frame(&src, opts);
+        assert_eq!(b[6], 200);
+
+        assert_eq!(FrameDecoder::new(&b).err(), Some(Error::UnknownTable(200)));
+        let decoder = FrameDecoder::with_tables(&b, &[table]).unwrap();
+        assert_eq!(decoder.options(), opts);
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        let zero_runs = Options {
+            table: SelectorTable::ZERO_RUNS,
+            ..Options::default()
+        };
+        let b = frame(&[0; 500], zero_runs);
+        assert_eq!(FrameDecoder::new(&b).unwrap().options(), zero_runs);
+    }
+
+    #[test]
+    fn test_frame_rejects_damage() {
+        let b = frame(&[1, 2, 3], Options::default());
+
+        let mut flipped = b.clone();
+        flipped[HEADER_LEN] ^= 0x10;
+        assert_eq!(
+            FrameDecoder::new(&flipped).err(),
+            Some(Error::ChecksumMismatch)
+        );
+
+        let mut magic = b.clone();
+        magic[0] = b'X';
+        assert_eq!(FrameDecoder::new(&magic).err(), Some(Error::InvalidMagic));
+
+        let mut version = b.clone();
+        version[4] = 9;
+        assert_eq!(
+            FrameDecoder::new(&version).err(),
+            Some(Error::UnsupportedVersion(9))
+        );
+
+        // An unknown flag next to a known one, under a valid checksum.
+        let mut flags = frame(
+            &[1, 2, 3],
+            Options {
+                run_values: true,
+                ..Options::default()
+            },
+        );
+        flags[5] |= 0x80;
+        let end = flags.len() - TRAILER_LEN;
+        let (body, trailer) = flags.split_at_mut(end);
+        BigEndian::write_u32(trailer, crc32c(body));
+        let err = FrameDecoder::new(&flags).err().unwrap();
+        assert_eq!(err, Error::InvalidFlags(0x80));
+        assert_eq!(err.to_string(), "unknown frame flags 0x80");
+
+        assert_eq!(
+            FrameDecoder::new(&b[..10]).err(),
+            Some(Error::TruncatedInput { remaining: 10 })
+        );
+    }
+
+    #[test]
+    fn test_frame_count_mismatch() {
+        // A header promising more values than the payload holds.
+        let mut b = frame(&[7], Options::default());
+        b[HEADER_LEN - 1] = 2;
+        let len = b.len() - TRAILER_LEN;
+        let crc = crc32c(&b[..len]);
+        b[len..].copy_from_slice(&crc.to_be_bytes());
+
+        let decoded: Vec<_> = FrameDecoder::new(&b).unwrap().collect();
+        assert_eq!(
+            decoded,
+            [
+                Ok(7),
+                Err(Error::CountMismatch {
+                    expected: 2,
+                    actual: 1
+                })
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Random access into packed words.
+
+use std::ops::Range;
+
+use crate::simple8b::{aligned_words, decode, header_len, Error, Options, BUF_SIZE};
+
+// Blocks per index entry. Lookups step through at most this many selectors.
+const GROUP: usize = 64;
+
+/// Maps value offsets to word offsets so single values and ranges can be
+/// decoded without walking the stream from the start.
+///
+/// Building reads only the selector of each word and keeps one entry per 64
+/// blocks. Lookups binary search the entries, step through the selectors of
+/// one group and unpack just the words they touch.
+#[derive(Clone)]
+pub struct Index<'a> {
+    words: &'a [u64],
+    swap: bool,
+    opts: Options,
+    // Offset of the first value of every GROUP-th block.
+    values: Vec<usize>,
+    // Offset of the first word of every GROUP-th block.
+    blocks: Vec<usize>,
+    len: usize,
+}
+
+impl<'a> Index<'a> {
+    pub fn new(words: &'a [u64]) -> Result<Self, Error> {
+        Self::with_options(words, Options::default())
+    }
+
+    /// Indexes `words`, checking every selector on the way.
+    pub fn with_options(words: &'a [u64], opts: Options) -> Result<Self, Error> {
+        Self::build(words, false, opts)
+    }
+
+    /// Indexes `bytes`, stored in `opts.endian` byte order, as words.
+    ///
+    /// `bytes` must start on an 8-byte boundary, like for
+    /// `WordsDecoder::from_bytes`.
+    pub fn from_bytes(bytes: &'a [u8], opts: Options) -> Result<Self, Error> {
+        let (words, swap) = aligned_words(bytes, opts.endian)?;
+        Self::build(words, swap, opts)
+    }
+
+    fn build(words: &'a [u64], swap: bool, opts: Options) -> Result<Self, Error> {
+        let mut index = Index {
+            words,
+            swap,
+            opts,
+            values: Vec::new(),
+            blocks: Vec::new(),
+            len: 0,
+        };
+        let mut b = 0;
+        let mut i = 0;
+        while i < words.len() {
+            let v = index.word(i);
+            let (n, w) = header_len(v, words.len() - i - 1, &opts)?;
+            if b % GROUP == 0 {
+                index.values.push(index.len);
+                index.blocks.push(i);
+            }
+            index.len += n;
+            b += 1;
+            i += w;
+        }
+        Ok(index)
+    }
+
+    /// Keeps only the first `count` values, the number the encoder was given,
+    /// so that padding such as a `tail` word's is out of bounds. Fails with
+    /// `CountMismatch` if the stream holds fewer.
+    pub fn limit(mut self, count: usize) -> Result<Self, Error> {
+        if count > self.len {
+            return Err(Error::CountMismatch {
+                expected: count as u64,
+                actual: self.len as u64,
+            });
+        }
+        self.len = count;
+        Ok(self)
+    }
+
+    /// Returns the number of values in the stream.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value at offset `i`, or `None` if it is out of bounds.
+    pub fn get(&self, i: usize) -> Option<u64> {
+        if i >= self.len() {
+            return None;
+        }
+        let (w, start) = self.block(i);
+        if self.opts.is_escape(self.word(w)) {
+            return Some(self.word(w + 1 + i - start));
+        }
+        let mut buf = [0; BUF_SIZE];
+        Some(self.unpack(w, &mut buf)[i - start])
+    }
+
+    /// Decodes the values in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds, like slice indexing.
+    pub fn decode_range(&self, range: Range<usize>) -> Vec<u64> {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "range {:?} out of bounds for {} values",
+            range,
+            self.len()
+        );
+        let mut dst = Vec::with_capacity(range.len());
+        if range.is_empty() {
+            return dst;
+        }
+        let mut buf = [0; BUF_SIZE];
+        let (mut w, mut start) = self.block(range.start);
+        while dst.len() < range.len() {
+            let (n, words) = self.block_len(w);
+            let from = range.start + dst.len() - start;
+            let to = n.min(range.end - start);
+            if self.opts.is_escape(self.word(w)) {
+                dst.extend((w + 1 + from..w + 1 + to).map(|r| self.word(r)));
+            } else {
+                dst.extend_from_slice(&self.unpack(w, &mut buf)[from..to]);
+            }
+            w += words;
+            start += n;
+        }
+        dst
+    }
+
+    // Returns the first word and first value of the block holding value `i`.
+    // Empty escape blocks share their start with the next block, so take
+    // the last entry starting at or before `i` and step past every block
+    // ending at or before it.
+    fn block(&self, i: usize) -> (usize, usize) {
+        let g = self.values.partition_point(|&v| v <= i) - 1;
+        let mut w = self.blocks[g];
+        let mut start = self.values[g];
+        loop {
+            let (n, words) = self.block_len(w);
+            if start + n > i {
+                return (w, start);
+            }
+            w += words;
+            start += n;
+        }
+    }
+
+    fn block_len(&self, w: usize) -> (usize, usize) {
+        header_len(self.word(w), self.words.len() - w - 1, &self.opts)
+            .expect("blocks are checked when indexing")
+    }
+
+    // Unpacks the packed word `w` into `buf`. Escape blocks are read in
+    // place instead.
+    fn unpack<'b>(&self, w: usize, buf: &'b mut [u64; BUF_SIZE]) -> &'b [u64] {
+        let n = decode(buf, self.word(w), &self.opts);
+        &buf[..n]
+    }
+
+    fn word(&self, w: usize) -> u64 {
+        if self.swap {
+            self.words[w].swap_bytes()
+        } else {
+            self.words[w]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple8b::{encode_all, Endian};
+
+    #[test]
+    fn test_index_get() {
+        let src: Vec<u64> = crate::sample(10_000);
+        let words = encode_all(&src).unwrap();
+        let index = Index::new(&words).unwrap();
+        assert_eq!(index.len(), src.len());
+        for i in (0..src.len()).step_by(7) {
+            assert_eq!(index.get(i), Some(src[i]));
+        }
+        assert_eq!(index.get(src.len()), None);
+
+        assert_eq!(index.decode_range(0..src.len()), src);
+        assert_eq!(index.decode_range(1234..5678), &src[1234..5678]);
+        assert_eq!(index.decode_range(17..18), &src[17..18]);
+        assert!(index.decode_range(50..50).is_empty());
+    }
+
+    #[test]
+    fn test_index_escape_blocks() {
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let mut src: Vec<u64> = (0..500).collect();
+        src.splice(100..100, [u64::MAX, u64::MAX - 1, u64::MAX - 2]);
+        let words = opts.encode_all(&src).unwrap();
+        let index = Index::with_options(&words, opts).unwrap();
+        assert_eq!(index.get(101), Some(u64::MAX - 1));
+        assert_eq!(index.decode_range(98..106), &src[98..106]);
+
+        // An escape block longer than one unpacked word.
+        let mut src: Vec<u64> = (0..300).collect();
+        src.splice(10..10, (0..500).map(|i| u64::MAX - i));
+        let words = opts.encode_all(&src).unwrap();
+        let index = Index::with_options(&words, opts).unwrap();
+        assert_eq!(index.get(400), Some(src[400]));
+        assert_eq!(index.decode_range(5..600), &src[5..600]);
+
+        // An escape word promising three raw words with only one after it.
+        assert_eq!(
+            Index::with_options(&[1 << 60 | 3, 7], opts).err(),
+            Some(Error::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_index_groups() {
+        // Enough blocks for several entries, with empty escape blocks on
+        // both sides of a group boundary.
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let mut words = Vec::new();
+        let mut src = Vec::new();
+        for b in 0..300u64 {
+            if b % 63 == 0 {
+                words.push(1 << 60);
+            }
+            words.push(15 << 60 | b);
+            src.push(b);
+        }
+        let index = Index::with_options(&words, opts).unwrap();
+        assert_eq!(index.blocks.len(), 305usize.div_ceil(GROUP));
+        assert_eq!(index.len(), src.len());
+        for (i, &v) in src.iter().enumerate() {
+            assert_eq!(index.get(i), Some(v));
+        }
+        assert_eq!(index.decode_range(60..250), &src[60..250]);
+    }
+
+    #[test]
+    fn test_index_from_bytes() {
+        let src: Vec<u64> = (0..5000).map(|i| (i * 31) % 1000).collect();
+        for endian in [Endian::Big, Endian::Little] {
+            let opts = Options {
+                endian,
+                ..Options::default()
+            };
+            let words: Vec<u64> = opts
+                .encode_all(&src)
+                .unwrap()
+                .into_iter()
+                .map(|w| match endian {
+                    Endian::Big => w.to_be(),
+                    Endian::Little => w.to_le(),
+                })
+                .collect();
+            // Safety: any initialised u64 is valid as bytes.
+            let bytes =
+                unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8) };
+            let index = Index::from_bytes(bytes, opts).unwrap();
+            assert_eq!(index.get(4321), Some(src[4321]));
+            assert_eq!(index.decode_range(100..3000), &src[100..3000]);
+            assert_eq!(
+                Index::from_bytes(&bytes[1..9], opts).err(),
+                Some(Error::Misaligned)
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_limit() {
+        // A padded 10×6 word of zeros two groups in, so the limit has to
+        // step over whole groups to reach it.
+        let mut words: Vec<u64> = (0..100).map(|b| 15 << 60 | b).collect();
+        words.push(7 << 60);
+        let index = Index::new(&words).unwrap();
+        assert_eq!(index.len(), 110);
+        let limited = index.clone().limit(103).unwrap();
+        assert_eq!(limited.len(), 103);
+        assert_eq!(limited.get(99), Some(99));
+        assert_eq!(limited.get(102), Some(0));
+        assert_eq!(limited.get(103), None);
+        assert_eq!(
+            limited.decode_range(60..103)[..40],
+            (60..100).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            index.limit(111).err(),
+            Some(Error::CountMismatch {
+                expected: 111,
+                actual: 110
+            })
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! InfluxDB TSM integer and timestamp blocks.
+//!
+//! The layouts follow `tsdb/engine/tsm1/int.go` and `timestamp.go`: the high
+//! nibble of the first byte names the encoding, and multi-byte fields are
+//! big-endian.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::delta::{zigzag_decode, zigzag_encode};
+use crate::simple8b::{self, Decoder, Error, MAX_VALUE};
+
+const UNCOMPRESSED: u8 = 0;
+const PACKED_SIMPLE: u8 = 1;
+const RLE: u8 = 2;
+
+/// Most values `decode_integers` and `decode_timestamps` expand an RLE block
+/// to.
+///
+/// An RLE block stores its length as a bare count, so without a bound a
+/// corrupt count could demand any amount of memory. InfluxDB writes at most
+/// 1000 values per block by default; use the `_max` variants to allow more.
+pub const MAX_BLOCK_LEN: usize = 1 << 20;
+
+/// Encodes an integer block.
+///
+/// Values are stored as zigzag deltas. Runs of an identical delta use the RLE
+/// form, deltas that fit in `MAX_VALUE` are packed with simple8b and anything
+/// else is written uncompressed.
+pub fn encode_integers(src: &[i64]) -> Vec<u8> {
+    let mut prev = 0i64;
+    let values: Vec<u64> = src
+        .iter()
+        .map(|&v| {
+            let delta = v.wrapping_sub(prev);
+            prev = v;
+            zigzag_encode(delta)
+        })
+        .collect();
+
+    // Only run-length encode if it could reduce storage size.
+    if values.len() > 2 && values[2..].iter().all(|&v| v == values[1]) {
+        let mut b = vec![RLE << 4];
+        put_u64(&mut b, values[0]);
+        put_uvarint(&mut b, values[1]);
+        put_uvarint(&mut b, (values.len() - 1) as u64);
+        return b;
+    }
+
+    if values.iter().any(|&v| v > MAX_VALUE) {
+        let mut b = vec![UNCOMPRESSED << 4];
+        for &v in &values {
+            put_u64(&mut b, v);
+        }
+        return b;
+    }
+
+    let (first, rest) = match values.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let encoded = simple8b::encode_all(rest).expect("deltas are range checked");
+    let mut b = vec![PACKED_SIMPLE << 4];
+    put_u64(&mut b, *first);
+    for v in encoded {
+        put_u64(&mut b, v);
+    }
+    b
+}
+
+/// Decodes an integer block produced by `encode_integers` or InfluxDB.
+pub fn decode_integers(b: &[u8]) -> Result<Vec<i64>, Error> {
+    decode_integers_max(b, MAX_BLOCK_LEN)
+}
+
+/// Decodes an integer block, failing with `BlockTooLong` if an RLE block
+/// claims more than `max` values.
+pub fn decode_integers_max(b: &[u8], max: usize) -> Result<Vec<i64>, Error> {
+    let (&header, b) = match b.split_first() {
+        Some(split) => split,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut deltas = Vec::new();
+    match header >> 4 {
+        UNCOMPRESSED => {
+            deltas = read_words(b)?;
+        }
+        PACKED_SIMPLE => {
+            deltas.push(read_u64(b)?);
+            for v in Decoder::new(&b[8..]) {
+                deltas.push(v?);
+            }
+        }
+        RLE => {
+            let first = read_u64(b)?;
+            let (delta, n) = read_uvarint(&b[8..])?;
+            let (count, _) = read_uvarint(&b[8 + n..])?;
+            check_len(count.saturating_add(1), max)?;
+            deltas.push(first);
+            deltas.extend((0..count).map(|_| delta));
+        }
+        encoding => return Err(Error::InvalidEncoding(encoding)),
+    }
+
+    let mut prev = 0i64;
+    Ok(deltas
+        .into_iter()
+        .map(|v| {
+            prev = prev.wrapping_add(zigzag_decode(v));
+            prev
+        })
+        .collect())
+}
+
+/// Encodes a timestamp block.
+///
+/// Deltas are scaled down by their largest common power-of-ten divisor, whose
+/// exponent is stored in the low nibble of the header.
+pub fn encode_timestamps(src: &[i64]) -> Vec<u8> {
+    let (&first, rest) = match src.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+
+    let mut max = 0u64;
+    let mut div = 1_000_000_000_000u64;
+    let mut prev = first as u64;
+    let deltas: Vec<u64> = rest
+        .iter()
+        .map(|&v| {
+            let delta = (v as u64).wrapping_sub(prev);
+            prev = v as u64;
+            max = max.max(delta);
+            while div > 1 && !delta.is_multiple_of(div) {
+                div /= 10;
+            }
+            delta
+        })
+        .collect();
+    let log10 = div.ilog10() as u8;
+
+    // The deltas are all the same, so we can run-length encode them.
+    if !deltas.is_empty() && deltas.iter().all(|&d| d == deltas[0]) {
+        let mut b = vec![RLE << 4 | log10];
+        put_u64(&mut b, first as u64);
+        put_uvarint(&mut b, deltas[0] / div);
+        put_uvarint(&mut b, src.len() as u64);
+        return b;
+    }
+
+    if max > MAX_VALUE {
+        let mut b = vec![UNCOMPRESSED << 4];
+        put_u64(&mut b, first as u64);
+        for d in deltas {
+            put_u64(&mut b, d);
+        }
+        return b;
+    }
+
+    let scaled: Vec<u64> = deltas.iter().map(|&d| d / div).collect();
+    let encoded = simple8b::encode_all(&scaled).expect("deltas are range checked");
+    let mut b = vec![PACKED_SIMPLE << 4 | log10];
+    put_u64(&mut b, first as u64);
+    for v in encoded {
+        put_u64(&mut b, v);
+    }
+    b
+}
+
+/// Decodes a timestamp block produced by `encode_timestamps` or InfluxDB.
+pub fn decode_timestamps(b: &[u8]) -> Result<Vec<i64>, Error> {
+    decode_timestamps_max(b, MAX_BLOCK_LEN)
+}
+
+/// Decodes a timestamp block, failing with `BlockTooLong` if an RLE block
+/// claims more than `max` values.
+pub fn decode_timestamps_max(b: &[u8], max: usize) -> Result<Vec<i64>, Error> {
+    let (&header, rest) = match b.split_first() {
+        Some(split) => split,
+        None => return Ok(Vec::new()),
+    };
+    let div = 10u64.pow(u32::from(header & 0xf));
+
+    let mut dst = Vec::new();
+    match header >> 4 {
+        UNCOMPRESSED => {
+            let mut prev = 0u64;
+            for (i, d) in read_words(rest)?.into_iter().enumerate() {
+                prev = if i == 0 { d } else { prev.wrapping_add(d) };
+                dst.push(prev as i64);
+            }
+        }
+        PACKED_SIMPLE => {
+            let mut prev = read_u64(rest)?;
+            dst.push(prev as i64);
+            for d in Decoder::new(&rest[8..]) {
+                prev = prev.wrapping_add(d?.wrapping_mul(div));
+                dst.push(prev as i64);
+            }
+        }
+        RLE => {
+            let first = read_u64(rest)?;
+            let (delta, n) = read_uvarint(&rest[8..])?;
+            let (count, _) = read_uvarint(&rest[8 + n..])?;
+            check_len(count, max)?;
+            let delta = delta.wrapping_mul(div);
+            let mut v = first;
+            for _ in 0..count {
+                dst.push(v as i64);
+                v = v.wrapping_add(delta);
+            }
+        }
+        encoding => return Err(Error::InvalidEncoding(encoding)),
+    }
+    Ok(dst)
+}
+
+fn check_len(len: u64, max: usize) -> Result<(), Error> {
+    if len > max as u64 {
+        return Err(Error::BlockTooLong { len, max });
+    }
+    Ok(())
+}
+
+fn put_u64(b: &mut Vec<u8>, v: u64) {
+    let mut word = [0u8; 8];
+    BigEndian::write_u64(&mut word, v);
+    b.extend_from_slice(&word);
+}
+
+fn read_u64(b: &[u8]) -> Result<u64, Error> {
+    if b.len() < 8 {
+        return Err(Error::TruncatedInput { remaining: b.len() });
+    }
+    Ok(BigEndian::read_u64(&b[..8]))
+}
+
+fn read_words(b: &[u8]) -> Result<Vec<u64>, Error> {
+    if !b.len().is_multiple_of(8) {
+        return Err(Error::TruncatedInput {
+            remaining: b.len() % 8,
+        });
+    }
+    Ok(b.chunks_exact(8).map(BigEndian::read_u64).collect())
+}
+
+// Same format as Go's `binary.PutUvarint`.
+fn put_uvarint(b: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        b.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    b.push(v as u8);
+}
+
+// Returns the value and the number of bytes read.
+fn read_uvarint(b: &[u8]) -> Result<(u64, usize), Error> {
+    let mut v = 0u64;
+    for (i, &byte) in b.iter().enumerate() {
+        if i == 10 || (i == 9 && byte > 1) {
+            return Err(Error::InvalidVarint);
+        }
+        v |= u64::from(byte & 0x7f) << (7 * i);
+        if byte < 0x80 {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(Error::TruncatedInput { remaining: b.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integers_rle() {
+        let b = encode_integers(&[1, 2, 3]);
+        assert_eq!(b, [0x20, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2]);
+        assert_eq!(decode_integers(&b).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_integers_packed() {
+        let b = encode_integers(&[0, 1, -1]);
+        let mut expected = vec![0x10, 0, 0, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(&(14u64 << 60 | 3 << 30 | 2).to_be_bytes());
+        assert_eq!(b, expected);
+        assert_eq!(decode_integers(&b).unwrap(), [0, 1, -1]);
+
+        let src: Vec<i64> = (0..1000).map(|i| (i * 7919) % 1000 - 500).collect();
+        assert_eq!(decode_integers(&encode_integers(&src)).unwrap(), src);
+    }
+
+    #[test]
+    fn test_integers_uncompressed() {
+        let src = [0, i64::MAX, i64::MIN, 7];
+        let b = encode_integers(&src);
+        assert_eq!(b[0] >> 4, UNCOMPRESSED);
+        assert_eq!(b.len(), 1 + 8 * src.len());
+        assert_eq!(decode_integers(&b).unwrap(), src);
+        assert!(decode_integers(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_timestamps_rle() {
+        let b = encode_timestamps(&[1000, 2000, 3000]);
+        let mut expected = vec![0x23];
+        expected.extend_from_slice(&1000u64.to_be_bytes());
+        expected.extend_from_slice(&[1, 3]);
+        assert_eq!(b, expected);
+        assert_eq!(decode_timestamps(&b).unwrap(), [1000, 2000, 3000]);
+    }
+
+    #[test]
+    fn test_timestamps_packed() {
+        let start = 1_700_000_000_000_000_000i64;
+        let src: Vec<i64> = (0..500)
+            .map(|i| start + i * 1_000_000_000 + (i % 3) * 1_000_000)
+            .collect();
+        let b = encode_timestamps(&src);
+        assert_eq!(b[0], PACKED_SIMPLE << 4 | 6);
+        assert_eq!(decode_timestamps(&b).unwrap(), src);
+
+        let single = encode_timestamps(&[start]);
+        assert_eq!(single[0], PACKED_SIMPLE << 4 | 12);
+        assert_eq!(decode_timestamps(&single).unwrap(), [start]);
+    }
+
+    #[test]
+    fn test_timestamps_uncompressed() {
+        let src = [0, 1, i64::MAX, 5];
+        let b = encode_timestamps(&src);
+        assert_eq!(b[0] >> 4, UNCOMPRESSED);
+        assert_eq!(decode_timestamps(&b).unwrap(), src);
+    }
+
+    #[test]
+    fn test_rle_count_bound() {
+        // A 15-byte block whose count claims about 2^32 values.
+        let mut b = vec![0x20, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        put_uvarint(&mut b, 1 << 32);
+        assert_eq!(b.len(), 15);
+        assert_eq!(
+            decode_integers(&b),
+            Err(Error::BlockTooLong {
+                len: (1 << 32) + 1,
+                max: MAX_BLOCK_LEN,
+            })
+        );
+
+        let mut b = vec![0x20, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        put_uvarint(&mut b, u64::MAX);
+        assert_eq!(
+            decode_timestamps(&b),
+            Err(Error::BlockTooLong {
+                len: u64::MAX,
+                max: MAX_BLOCK_LEN,
+            })
+        );
+
+        let b = encode_integers(&[5; 10]);
+        assert_eq!(decode_integers_max(&b, 10).unwrap(), [5; 10]);
+        assert_eq!(
+            decode_integers_max(&b, 9),
+            Err(Error::BlockTooLong { len: 10, max: 9 })
+        );
+        let b = encode_timestamps(&[10, 20, 30]);
+        assert_eq!(decode_timestamps_max(&b, 3).unwrap(), [10, 20, 30]);
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        assert_eq!(decode_integers(&[0x30]), Err(Error::InvalidEncoding(3)));
+        assert_eq!(
+            decode_timestamps(&[0x10, 1, 2]),
+            Err(Error::TruncatedInput { remaining: 2 })
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Adapters between simple8b streams and `std::io`.
+
+use std::io::{self, Read, Write};
+
+use crate::simple8b::{decode, Encoder, Error, Options, BUF_SIZE, MAX_VALUE};
+
+/// Encoder that writes packed words to `W` as soon as they are final.
+///
+/// Up to one window of values stays buffered until `finish` packs it, so a
+/// writer dropped without calling `finish` loses its tail.
+pub struct EncoderWriter<W: Write> {
+    inner: W,
+    encoder: Encoder,
+}
+
+impl<W: Write> EncoderWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, Options::default())
+    }
+
+    pub fn with_options(inner: W, opts: Options) -> Self {
+        EncoderWriter {
+            inner,
+            encoder: Encoder::with_options(opts),
+        }
+    }
+
+    /// Buffers `v` and writes out any words it completes.
+    ///
+    /// A value the encoder rejects is reported as `InvalidData`, with the
+    /// underlying `Error` as its source.
+    pub fn write(&mut self, v: u64) -> io::Result<()> {
+        self.encoder.write(v)?;
+        self.drain()
+    }
+
+    /// Writes every value of `src`.
+    pub fn write_all(&mut self, src: &[u64]) -> io::Result<()> {
+        for &v in src {
+            self.encoder.write(v)?;
+        }
+        self.drain()
+    }
+
+    /// Packs the buffered values, flushes the writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encoder.flush_all();
+        self.drain()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Returns the writer, discarding any values not yet packed.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        if !self.encoder.bytes().is_empty() {
+            self.inner.write_all(self.encoder.bytes())?;
+            self.encoder.clear_bytes();
+        }
+        Ok(())
+    }
+}
+
+/// Decoder that pulls words from `R` as values are needed.
+///
+/// Each word is a separate small read, so wrap unbuffered sources such as a
+/// `File` in a `BufReader`. Like `Decoder`, it yields one `Result` per value
+/// and stops after the first error; decode errors arrive as `InvalidData`.
+pub struct DecoderReader<R: Read> {
+    inner: R,
+    unpacker: Unpacker,
+    done: bool,
+}
+
+impl<R: Read> DecoderReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_options(inner, Options::default())
+    }
+
+    pub fn with_options(inner: R, opts: Options) -> Self {
+        DecoderReader {
+            inner,
+            unpacker: Unpacker::new(opts),
+            done: false,
+        }
+    }
+
+    /// Stops after exactly `count` values, like `Decoder::limit`.
+    ///
+    /// Nothing past them is read, and input that ends before them fails with
+    /// `CountMismatch`.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.unpacker.set_limit(count);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reads the next word, or `None` at a clean end of input.
+    fn read_word(&mut self) -> io::Result<Option<[u8; 8]>> {
+        let mut word = [0u8; 8];
+        let mut filled = 0;
+        while filled < word.len() {
+            match self.inner.read(&mut word[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(Error::TruncatedInput { remaining: filled }.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(word))
+    }
+
+    fn read_value(&mut self) -> io::Result<Option<u64>> {
+        loop {
+            if let Some(v) = self.unpacker.pop() {
+                return Ok(Some(v));
+            }
+            if self.unpacker.is_full() {
+                return Ok(None);
+            }
+            match self.read_word()? {
+                Some(v) => self.unpacker.push(v)?,
+                None => {
+                    self.unpacker.end()?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for DecoderReader<R> {
+    type Item = io::Result<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let r = self.read_value();
+        self.done = !matches!(r, Ok(Some(_)));
+        r.transpose()
+    }
+}
+
+// Turns words into values one at a time for the reader adapters, which cannot
+// borrow their input the way `Decoder` does.
+pub(crate) struct Unpacker {
+    buf: [u64; BUF_SIZE],
+    i: usize,
+    n: usize,
+    // Raw words still owed by the current escape block.
+    raw: usize,
+    opts: Options,
+    // Values to yield in total, if known, and values yielded so far.
+    limit: Option<usize>,
+    taken: usize,
+}
+
+impl Unpacker {
+    pub(crate) fn new(opts: Options) -> Self {
+        Unpacker {
+            buf: [0; BUF_SIZE],
+            i: 0,
+            n: 0,
+            raw: 0,
+            opts,
+            limit: None,
+            taken: 0,
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, count: usize) {
+        self.limit = Some(count);
+    }
+
+    /// Returns true once the limit, if any, has been reached.
+    pub(crate) fn is_full(&self) -> bool {
+        self.limit == Some(self.taken)
+    }
+
+    /// Returns the next buffered value, if any.
+    pub(crate) fn pop(&mut self) -> Option<u64> {
+        if self.is_full() {
+            return None;
+        }
+        let v = self.buf[..self.n].get(self.i).copied()?;
+        self.i += 1;
+        self.taken += 1;
+        Some(v)
+    }
+
+    /// Unpacks the next word; only call once `pop` has run dry.
+    pub(crate) fn push(&mut self, word: [u8; 8]) -> Result<(), Error> {
+        let v = self.opts.endian.read(&word);
+        self.i = 0;
+        if self.raw > 0 {
+            self.raw -= 1;
+            self.buf[0] = v;
+            self.n = 1;
+        } else if self.opts.is_escape(v) {
+            self.raw = (v & MAX_VALUE) as usize;
+            self.n = 0;
+        } else {
+            self.n = decode(&mut self.buf, v, &self.opts);
+        }
+        Ok(())
+    }
+
+    /// Checks that the input may end here.
+    pub(crate) fn end(&self) -> Result<(), Error> {
+        if self.raw > 0 {
+            return Err(Error::UnexpectedEnd);
+        }
+        match self.limit {
+            Some(limit) if self.taken < limit => Err(Error::CountMismatch {
+                expected: limit as u64,
+                actual: self.taken as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple8b;
+
+    // Hands out at most three bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_encoder_writer() {
+        let src: Vec<u64> = crate::sample(10_000);
+        let mut writer = EncoderWriter::new(Vec::new());
+        for &v in &src[..5000] {
+            writer.write(v).unwrap();
+        }
+        // Words go out as they are produced, not all at the end.
+        assert!(!writer.get_ref().is_empty());
+        writer.write_all(&src[5000..]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut encoder = simple8b::Encoder::new();
+        encoder.set_values(&src).unwrap();
+        assert_eq!(bytes, encoder.finish());
+    }
+
+    #[test]
+    fn test_encoder_writer_errors() {
+        let mut writer = EncoderWriter::new(Vec::new());
+        writer.write(1).unwrap();
+        let err = writer.write(u64::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.into_inner().unwrap().downcast_ref::<Error>(),
+            Some(&Error::ValueTooLarge {
+                index: 1,
+                value: u64::MAX,
+            })
+        );
+
+        // Values that were never packed are dropped by `into_inner`.
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_decoder_reader() {
+        let src: Vec<u64> = crate::sample(10_000);
+        let mut encoder = simple8b::Encoder::new();
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+
+        let decoded: io::Result<Vec<u64>> = DecoderReader::new(Trickle(&bytes)).collect();
+        assert_eq!(decoded.unwrap(), src);
+        assert!(DecoderReader::new(io::empty()).next().is_none());
+    }
+
+    #[test]
+    fn test_decoder_reader_partial_word() {
+        let mut bytes = (15u64 << 60 | 42).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let mut reader = DecoderReader::new(Trickle(&bytes));
+        assert_eq!(reader.next().unwrap().unwrap(), 42);
+
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.into_inner().unwrap().downcast_ref::<Error>(),
+            Some(&Error::TruncatedInput { remaining: 3 })
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_decoder_reader_limit() {
+        // One 10×6 word of zeros, handed out a few bytes at a time; the
+        // limit drops its padding and still notices a short count.
+        let bytes = (7u64 << 60).to_be_bytes();
+        let decoded: io::Result<Vec<u64>> = DecoderReader::new(Trickle(&bytes)).limit(4).collect();
+        assert_eq!(decoded.unwrap(), [0; 4]);
+
+        let mut reader = DecoderReader::new(Trickle(&bytes)).limit(12);
+        let err = reader.nth(10).unwrap().unwrap_err();
+        assert_eq!(
+            err.into_inner().unwrap().downcast_ref::<Error>(),
+            Some(&Error::CountMismatch {
+                expected: 12,
+                actual: 10
+            })
+        );
+    }
+}
@@ -1,16 +1,36 @@
-mod simple8b;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod delta;
+pub mod frame;
+pub mod index;
+pub mod influx;
+pub mod io;
+mod simd;
+pub mod simple16;
+pub mod simple8b;
+pub mod simple9;
+mod stream;
+mod word32;
 
+#[cfg(test)]
 use crate::simple8b::Encoder;
 
+// Scattered values below 3000: the round-trip fixture shared by the codec
+// and adapter tests.
+#[cfg(test)]
+pub(crate) fn sample<T: From<u16>>(len: usize) -> Vec<T> {
+    (0..len)
+        .map(|i| T::from((i * 7919 % 3000) as u16))
+        .collect()
+}
+
 #[test]
 fn test_create_encoder() {
-    let encoder = Encoder {
-        buf: [1; 240],
-        h: 0,
-        t: 0,
-        bp: 0,
-        bytes: [1; 1920],
-        b: [1; 8],
-    };
-    println!("{:?}", encoder.buf);
-}
\ No newline at end of file
+    let mut encoder = Encoder::new();
+    for i in 0..1000 {
+        encoder.write(i % 16).unwrap();
+    }
+    assert!(!encoder.bytes().is_empty());
+    let bytes = encoder.finish();
+    assert_eq!(bytes.len() % 8, 0);
+}
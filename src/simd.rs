@@ -0,0 +1,189 @@
+//! Vectorised kernels for bulk decoding.
+//!
+//! Each unpack table is indexed by selector, like `SELECTOR`, and holds one
+//! kernel per packing. `unpackers` picks the widest instruction set the CPU
+//! supports at runtime and falls back to portable scalar code otherwise.
+
+use crate::simple8b::{unpack, unpack_run};
+
+/// Unpacks the fields of a word into the start of `dst`.
+///
+/// Unsafe because the SIMD kernels need their CPU features to be present.
+pub(crate) type Unpack = unsafe fn(u64, &mut [u64]);
+
+// Run selectors carry no payload, and from 6×10 on a word holds too few
+// values for vectors to beat the scalar kernels, so every table shares the
+// scalar kernels there.
+macro_rules! table {
+    ($unpack:ident) => {
+        [
+            unpack_run::<240>,
+            unpack_run::<120>,
+            $unpack::<60, 1>,
+            $unpack::<30, 2>,
+            $unpack::<20, 3>,
+            $unpack::<15, 4>,
+            $unpack::<12, 5>,
+            $unpack::<10, 6>,
+            $unpack::<8, 7>,
+            $unpack::<7, 8>,
+            unpack::<6, 10>,
+            unpack::<5, 12>,
+            unpack::<4, 15>,
+            unpack::<3, 20>,
+            unpack::<2, 30>,
+            unpack::<1, 60>,
+        ]
+    };
+}
+
+const SCALAR: [Unpack; 16] = table!(unpack);
+
+/// Returns the fastest kernel table for the running CPU.
+///
+/// The returned kernels are safe to call once `dst` holds enough room for the
+/// selector's value count.
+pub(crate) fn unpackers() -> &'static [Unpack; 16] {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return &x86::AVX2;
+        }
+        if is_x86_feature_detected!("sse4.1") {
+            return &x86::SSE41;
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return &aarch64::NEON;
+        }
+    }
+    &SCALAR
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{unpack, unpack_run, Unpack};
+
+    pub(super) const AVX2: [Unpack; 16] = table!(unpack_avx2);
+    pub(super) const SSE41: [Unpack; 16] = table!(unpack_sse41);
+
+    // Shifts four copies of the word by four consecutive field offsets at once.
+    #[target_feature(enable = "avx2")]
+    unsafe fn unpack_avx2<const N: usize, const BITS: usize>(v: u64, dst: &mut [u64]) {
+        let dst = &mut dst[..N];
+        let mask = (1u64 << BITS) - 1;
+        let lanes = _mm256_set1_epi64x(mask as i64);
+        let word = _mm256_set1_epi64x(v as i64);
+        let step = _mm256_set1_epi64x(4 * BITS as i64);
+        let b = BITS as i64;
+        let mut shift = _mm256_setr_epi64x(0, b, 2 * b, 3 * b);
+        let mut i = 0;
+        while i + 4 <= N {
+            let x = _mm256_and_si256(_mm256_srlv_epi64(word, shift), lanes);
+            _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, x);
+            shift = _mm256_add_epi64(shift, step);
+            i += 4;
+        }
+        for (j, d) in dst.iter_mut().enumerate().skip(i) {
+            *d = (v >> (j * BITS)) & mask;
+        }
+    }
+
+    // SSE has no per-lane 64-bit shift, so shift twice and blend the halves.
+    #[target_feature(enable = "sse4.1")]
+    unsafe fn unpack_sse41<const N: usize, const BITS: usize>(v: u64, dst: &mut [u64]) {
+        let dst = &mut dst[..N];
+        let mask = (1u64 << BITS) - 1;
+        let lanes = _mm_set1_epi64x(mask as i64);
+        let word = _mm_set1_epi64x(v as i64);
+        let mut i = 0;
+        while i + 2 <= N {
+            let lo = _mm_srl_epi64(word, _mm_cvtsi64_si128((i * BITS) as i64));
+            let hi = _mm_srl_epi64(word, _mm_cvtsi64_si128(((i + 1) * BITS) as i64));
+            let x = _mm_and_si128(_mm_blend_epi16::<0xf0>(lo, hi), lanes);
+            _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, x);
+            i += 2;
+        }
+        for (j, d) in dst.iter_mut().enumerate().skip(i) {
+            *d = (v >> (j * BITS)) & mask;
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    use super::{unpack, unpack_run, Unpack};
+
+    pub(super) const NEON: [Unpack; 16] = table!(unpack_neon);
+
+    // NEON shifts right by shifting left with a negative count.
+    #[target_feature(enable = "neon")]
+    unsafe fn unpack_neon<const N: usize, const BITS: usize>(v: u64, dst: &mut [u64]) {
+        let dst = &mut dst[..N];
+        let mask = (1u64 << BITS) - 1;
+        let lanes = vdupq_n_u64(mask);
+        let word = vdupq_n_u64(v);
+        let step = vdupq_n_s64(2 * BITS as i64);
+        let offsets = [0, -(BITS as i64)];
+        let mut shift = vld1q_s64(offsets.as_ptr());
+        let mut i = 0;
+        while i + 2 <= N {
+            let x = vandq_u64(vshlq_u64(word, shift), lanes);
+            vst1q_u64(dst.as_mut_ptr().add(i), x);
+            shift = vsubq_s64(shift, step);
+            i += 2;
+        }
+        for (j, d) in dst.iter_mut().enumerate().skip(i) {
+            *d = (v >> (j * BITS)) & mask;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every table the CPU supports must agree with the scalar kernels.
+    #[test]
+    fn test_kernels_match_scalar() {
+        let mut tables = vec![&SCALAR];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                tables.push(&x86::AVX2);
+            }
+            if is_x86_feature_detected!("sse4.1") {
+                tables.push(&x86::SSE41);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                tables.push(&aarch64::NEON);
+            }
+        }
+
+        let mut x = 0x9e37_79b9_7f4a_7c15u64;
+        for sel in 0..16u64 {
+            for _ in 0..64 {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                let v = sel << 60 | x >> 4;
+                let mut want = [u64::MAX; 241];
+                unsafe { SCALAR[sel as usize](v, &mut want) };
+                for table in &tables {
+                    let mut got = [u64::MAX; 241];
+                    unsafe { table[sel as usize](v, &mut got) };
+                    assert_eq!(got, want, "selector {sel}, word {v:#x}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Simple-16: 32-bit words with a 4-bit selector naming one of sixteen
+//! packings of the 28 data bits, some of which mix two or three widths.
+//!
+//! Uses the table of Zhang, Long and Suel, so it interoperates with other
+//! Simple-16 implementations that fill words from the low bits up. The API
+//! mirrors `simple8b` and reports the same `Error`.
+
+pub use crate::simple8b::{Endian, Error};
+pub use crate::word32::MAX_VALUE;
+use crate::word32::{self, Packing, Scheme};
+
+/// Selector table of Simple-16.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simple16;
+
+impl Scheme for Simple16 {
+    const TABLE: &'static [Packing] = &[
+        Packing::new(&[(28, 1)]),
+        Packing::new(&[(7, 2), (14, 1)]),
+        Packing::new(&[(7, 1), (7, 2), (7, 1)]),
+        Packing::new(&[(14, 1), (7, 2)]),
+        Packing::new(&[(14, 2)]),
+        Packing::new(&[(1, 4), (8, 3)]),
+        Packing::new(&[(1, 3), (4, 4), (3, 3)]),
+        Packing::new(&[(7, 4)]),
+        Packing::new(&[(4, 5), (2, 4)]),
+        Packing::new(&[(2, 4), (4, 5)]),
+        Packing::new(&[(3, 6), (2, 5)]),
+        Packing::new(&[(2, 5), (3, 6)]),
+        Packing::new(&[(4, 7)]),
+        Packing::new(&[(1, 10), (2, 9)]),
+        Packing::new(&[(2, 14)]),
+        Packing::new(&[(1, 28)]),
+    ];
+}
+
+/// Streaming Simple-16 encoder.
+pub type Encoder = word32::Encoder<Simple16>;
+
+/// Decoder over a stream of Simple-16 words.
+pub type Decoder<'a> = word32::Decoder<'a, Simple16>;
+
+/// Iterator returned by `Decoder::trusted`.
+pub type TrustedDecoder<'a> = word32::TrustedDecoder<'a, Simple16>;
+
+/// Packs `src` into Simple-16 words. Every value must be at most `MAX_VALUE`.
+pub fn encode_all(src: &[u32]) -> Result<Vec<u32>, Error> {
+    word32::encode_all::<Simple16>(src)
+}
+
+/// Packs `src` into `dst` and returns the number of words written.
+pub fn encode_all_into(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    word32::encode_all_into::<Simple16>(src, dst)
+}
+
+/// Unpacks every word of `src`.
+pub fn decode_all(src: &[u32]) -> Result<Vec<u32>, Error> {
+    word32::decode_all::<Simple16>(src)
+}
+
+/// Unpacks every word of `src` into `dst` and returns the number of values
+/// written.
+pub fn decode_all_into(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    word32::decode_all_into::<Simple16>(src, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple16_round_trip() {
+        let src: Vec<u32> = crate::sample(10_000);
+        let words = encode_all(&src).unwrap();
+        assert_eq!(decode_all(&words).unwrap(), src);
+
+        let mut encoder = Encoder::new();
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        let decoded: Result<Vec<u32>, Error> = Decoder::new(&bytes).collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        let mut dst = vec![0; src.len()];
+        assert_eq!(decode_all_into(&words, &mut dst), Ok(src.len()));
+        assert_eq!(dst, src);
+
+        let mut encoder = Encoder::with_endian(Endian::Little);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        let mut decoder = Decoder::with_endian(&bytes, Endian::Little).limit(5000);
+        assert_eq!(decoder.nth(4321), Some(Ok(src[4321])));
+        assert_eq!(decoder.trusted().count(), 5000 - 4322);
+    }
+
+    #[test]
+    fn test_simple16_mixed_widths() {
+        // Seven 2-bit values then fourteen 1-bit values share selector 1,
+        // where Simple-9 would need two words.
+        let mut src = vec![3; 7];
+        src.extend_from_slice(&[1; 14]);
+        let words = encode_all(&src).unwrap();
+        assert_eq!(words, [1 << 28 | 0x0fff_ffff]);
+        assert_eq!(crate::simple9::encode_all(&src).unwrap().len(), 2);
+        assert_eq!(decode_all(&words).unwrap(), src);
+
+        // Every selector is valid, so only a wide value can fail.
+        assert!(decode_all(&[u32::MAX]).is_ok());
+        assert_eq!(
+            encode_all(&[u32::MAX]),
+            Err(Error::ValueTooLarge {
+                index: 0,
+                value: u64::from(u32::MAX),
+            })
+        );
+    }
+}
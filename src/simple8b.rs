@@ -1,146 +1,1084 @@
-use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::delta::{zigzag_decode, zigzag_encode};
+use crate::simd;
+use crate::stream::{self, Source, Stream};
+
+/// Largest value that fits in a simple8b word.
+pub const MAX_VALUE: u64 = (1 << 60) - 1;
+pub(crate) const BUF_SIZE: usize = 240;
+// Selector of the escape word when `Options::escape` is set.
+const ESCAPE: u64 = 1;
+
+/// Errors reported by the encode and decode paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The value at `index` is above the codec's `MAX_VALUE`.
+    ValueTooLarge { index: usize, value: u64 },
+    /// A word carries a selector that has no packing. Only simple9 leaves
+    /// selectors unused; every simple8b and simple16 selector is valid.
+    InvalidSelector(u8),
+    /// The input ends with `remaining` bytes, too few for a whole word.
+    TruncatedInput { remaining: usize },
+    /// The destination buffer is too short for the output.
+    OutputTooSmall,
+    /// A block header names an unknown encoding.
+    InvalidEncoding(u8),
+    /// A varint runs past 64 bits.
+    InvalidVarint,
+    /// The input ends inside an escape block.
+    UnexpectedEnd,
+    /// A byte buffer to be viewed as words is not 8-byte aligned.
+    Misaligned,
+    /// A frame does not start with the frame magic.
+    InvalidMagic,
+    /// A frame was written by an unknown format version.
+    UnsupportedVersion(u8),
+    /// A frame's checksum does not match its contents.
+    ChecksumMismatch,
+    /// The input holds `actual` values where `expected` were recorded, in a
+    /// frame header or through `Decoder::limit`.
+    CountMismatch { expected: u64, actual: u64 },
+    /// A selector table passed to `SelectorTable::new` cannot be used.
+    InvalidTable,
+    /// A frame names a selector table that is neither a preset nor one the
+    /// caller supplied.
+    UnknownTable(u8),
+    /// A block holds `len` values, more than the `max` the caller allows.
+    BlockTooLong { len: u64, max: usize },
+    /// A frame header sets option flags this version does not know; carries
+    /// just the unknown bits.
+    InvalidFlags(u8),
+}
+
+impl Error {
+    // Rebases an index reported for a sub-slice onto the whole input.
+    pub(crate) fn at(self, offset: usize) -> Self {
+        match self {
+            Error::ValueTooLarge { index, value } => Error::ValueTooLarge {
+                index: index + offset,
+                value,
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ValueTooLarge { index, value } => {
+                write!(f, "value {} at index {} is too large to pack", value, index)
+            }
+            Error::InvalidSelector(sel) => write!(f, "invalid selector {}", sel),
+            Error::TruncatedInput { remaining } => {
+                write!(f, "input ends with a partial word of {} bytes", remaining)
+            }
+            Error::OutputTooSmall => write!(f, "output buffer too small"),
+            Error::InvalidEncoding(enc) => write!(f, "invalid block encoding {}", enc),
+            Error::InvalidVarint => write!(f, "varint overflows 64 bits"),
+            Error::UnexpectedEnd => write!(f, "input ends inside an escape block"),
+            Error::Misaligned => write!(f, "input is not aligned to 8 bytes"),
+            Error::InvalidMagic => write!(f, "input is not a simple8b frame"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported frame version {}", v),
+            Error::ChecksumMismatch => write!(f, "frame checksum mismatch"),
+            Error::CountMismatch { expected, actual } => {
+                write!(f, "input holds {} values, expected {}", actual, expected)
+            }
+            Error::InvalidTable => write!(f, "invalid selector table"),
+            Error::UnknownTable(id) => write!(f, "unknown selector table {}", id),
+            Error::BlockTooLong { len, max } => {
+                write!(f, "block holds {} values, more than {}", len, max)
+            }
+            Error::InvalidFlags(flags) => write!(f, "unknown frame flags {:#04x}", flags),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Options shared by the encode and decode paths.
+///
+/// Both sides of a stream must use the same options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Lets run selectors encode runs of any value rather than only runs of
+    /// the table's run value. The repeated value is stored in the 60 payload
+    /// bits of the run word, so a constant series costs one word per 240
+    /// values.
+    pub run_values: bool,
+    /// Lets values above `MAX_VALUE` through. A run of them is written as an
+    /// escape word, selector 1 with the run length in its payload, followed
+    /// by the raw values. Selector 1 then no longer encodes runs of 120.
+    pub escape: bool,
+    /// Packs a short final block into a single word instead of spending a
+    /// word per value on it. The word is padded with zeros, or with the run
+    /// value when a run selector fits, and decoders return the padding as
+    /// trailing values. Keep the value count, as a frame does, and decode
+    /// with `Options::decode_all_n` or the `limit` of a decoder or `Index`.
+    pub tail: bool,
+    /// How the slice API chooses selectors. The streaming `Encoder` always
+    /// packs greedily.
+    pub strategy: EncodeStrategy,
+    /// Byte order of the words written by `Encoder` and read by `Decoder` and
+    /// the `io` adapters. The slice API works on whole `u64`s and ignores it.
+    pub endian: Endian,
+    /// Packing of each selector.
+    pub table: SelectorTable,
+}
+
+/// Byte order of packed words in a byte stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    /// Most significant byte first, as InfluxDB writes them.
+    #[default]
+    Big,
+    /// Least significant byte first, so the bytes can be viewed as native
+    /// words on little-endian machines.
+    Little,
+}
+
+impl Endian {
+    pub(crate) fn read(self, b: &[u8]) -> u64 {
+        match self {
+            Endian::Big => BigEndian::read_u64(b),
+            Endian::Little => LittleEndian::read_u64(b),
+        }
+    }
+
+    pub(crate) fn bytes(self, v: u64) -> [u8; 8] {
+        match self {
+            Endian::Big => v.to_be_bytes(),
+            Endian::Little => v.to_le_bytes(),
+        }
+    }
+
+    pub(crate) fn read32(self, b: &[u8]) -> u32 {
+        match self {
+            Endian::Big => BigEndian::read_u32(b),
+            Endian::Little => LittleEndian::read_u32(b),
+        }
+    }
+
+    pub(crate) fn bytes32(self, v: u32) -> [u8; 4] {
+        match self {
+            Endian::Big => v.to_be_bytes(),
+            Endian::Little => v.to_le_bytes(),
+        }
+    }
+}
+
+/// The packing behind each of the 16 selectors.
+///
+/// Each entry is a value count and a bit width. Entries with a width of 0
+/// are runs: they carry no payload and stand for that many copies of the
+/// table's run value, or with `Options::run_values`, of the value in the
+/// payload. With `Options::escape`, selector 1 is taken for escape words
+/// whatever its entry says.
+///
+/// The presets use the widths of `INFLUX`, which have vectorised kernels;
+/// other tables are packed and unpacked by scalar code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorTable {
+    id: u8,
+    entries: [(u8, u8); 16],
+    run: u64,
+    // Whether the entries are those of `INFLUX`, so the kernels apply.
+    fast: bool,
+}
+
+const INFLUX_ENTRIES: [(u8, u8); 16] = [
+    (240, 0),
+    (120, 0),
+    (60, 1),
+    (30, 2),
+    (20, 3),
+    (15, 4),
+    (12, 5),
+    (10, 6),
+    (8, 7),
+    (7, 8),
+    (6, 10),
+    (5, 12),
+    (4, 15),
+    (3, 20),
+    (2, 30),
+    (1, 60),
+];
+
+impl SelectorTable {
+    /// The InfluxDB table, whose run selectors stand for runs of 1. Table id
+    /// 0 and the default.
+    pub const INFLUX: Self = SelectorTable {
+        id: 0,
+        entries: INFLUX_ENTRIES,
+        run: 1,
+        fast: true,
+    };
+
+    /// The InfluxDB widths with runs of 0 instead of 1, for sparse series
+    /// and the deltas of constant ones. Table id 1.
+    pub const ZERO_RUNS: Self = SelectorTable {
+        id: 1,
+        entries: INFLUX_ENTRIES,
+        run: 0,
+        fast: true,
+    };
+
+    /// Ids below this are reserved for presets.
+    pub const FIRST_CUSTOM_ID: u8 = 128;
+
+    /// Builds a table from `(count, bits)` entries indexed by selector, with
+    /// run entries standing for `run`.
+    ///
+    /// Fails with `InvalidTable` if `id` is below `FIRST_CUSTOM_ID`, if
+    /// `run` is above `MAX_VALUE`, if an entry holds no values, more than
+    /// 240, or more than 60 bits, or if no selector other than 1 has a
+    /// 60-bit entry, which every value must be able to fall back to.
+    pub fn new(id: u8, entries: [(usize, usize); 16], run: u64) -> Result<Self, Error> {
+        if id < Self::FIRST_CUSTOM_ID || run > MAX_VALUE {
+            return Err(Error::InvalidTable);
+        }
+        let valid = entries
+            .iter()
+            .all(|&(n, bits)| n > 0 && n <= BUF_SIZE && n * bits <= 60);
+        let wide = entries
+            .iter()
+            .enumerate()
+            .any(|(sel, &(_, bits))| sel as u64 != ESCAPE && bits == 60);
+        if !valid || !wide {
+            return Err(Error::InvalidTable);
+        }
+        let entries = entries.map(|(n, bits)| (n as u8, bits as u8));
+        Ok(SelectorTable {
+            id,
+            entries,
+            run,
+            fast: entries == INFLUX_ENTRIES,
+        })
+    }
+
+    /// Returns the preset with table id `id`, if there is one.
+    pub fn preset(id: u8) -> Option<Self> {
+        [Self::INFLUX, Self::ZERO_RUNS]
+            .into_iter()
+            .find(|t| t.id == id)
+    }
+
+    /// Returns the id a frame records for this table.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns the value count and bit width of selector `sel`.
+    pub fn entry(&self, sel: usize) -> (usize, usize) {
+        let (n, bits) = self.entries[sel];
+        (n as usize, bits as usize)
+    }
+}
+
+impl Default for SelectorTable {
+    fn default() -> Self {
+        Self::INFLUX
+    }
+}
+
+/// Selector choice used by `Options::encode_all`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodeStrategy {
+    /// Takes the densest selector that fits at each position.
+    #[default]
+    Greedy,
+    /// Minimises the total number of output words by dynamic programming over
+    /// the whole input, at the cost of a slower encode.
+    Optimal,
+}
+
+impl Options {
+    /// Packs `src` into simple8b words.
+    pub fn encode_all<T: Simple8bInt>(&self, src: &[T]) -> Result<Vec<u64>, Error> {
+        let mut dst = Vec::with_capacity(src.len());
+        self.encode_values(src, |v| {
+            dst.push(v);
+            Ok(())
+        })?;
+        Ok(dst)
+    }
+
+    /// Packs `src` into `dst` and returns the number of words written.
+    pub fn encode_all_into<T: Simple8bInt>(
+        &self,
+        src: &[T],
+        dst: &mut [u64],
+    ) -> Result<usize, Error> {
+        let mut j = 0;
+        self.encode_values(src, |v| {
+            if j >= dst.len() {
+                return Err(Error::OutputTooSmall);
+            }
+            dst[j] = v;
+            j += 1;
+            Ok(())
+        })?;
+        Ok(j)
+    }
 
-#[allow(dead_code)]
-const MAX_VALUE: u64 = (1 << 60) - 1;
-const BUF_SIZE: usize = 240;
+    // Types other than `u64` are widened a window at a time, except by the
+    // optimal strategy, which needs the whole input at once.
+    fn encode_values<T: Simple8bInt, F>(&self, src: &[T], mut emit: F) -> Result<(), Error>
+    where
+        F: FnMut(u64) -> Result<(), Error>,
+    {
+        if let Some(src) = T::as_u64_slice(src) {
+            return self.encode_words(src, emit);
+        }
+        if self.strategy == EncodeStrategy::Optimal {
+            let src: Vec<u64> = src.iter().map(|v| v.to_u64()).collect();
+            return encode_optimal(&src, self, emit);
+        }
+
+        // A block looks at most BUF_SIZE values ahead, so a window that does
+        // not end the input is only packed while more than that is left.
+        let mut buf = [0; 2 * BUF_SIZE];
+        let mut i = 0;
+        while i < src.len() {
+            let window = &mut buf[..(src.len() - i).min(2 * BUF_SIZE)];
+            for (w, v) in window.iter_mut().zip(&src[i..]) {
+                *w = v.to_u64();
+            }
+            let last = i + window.len() == src.len();
+            let mut k = 0;
+            while k < window.len() && (last || window.len() - k > BUF_SIZE) {
+                if let Some(sel) = tail_selector(&window[k..], self) {
+                    return emit(pack_word(sel, &window[k..], self));
+                }
+                if self.escape && window[k] > MAX_VALUE {
+                    // Escape runs have no length bound, so count them over
+                    // the whole input rather than the window.
+                    let run = src[i + k..]
+                        .iter()
+                        .take_while(|v| v.to_u64() > MAX_VALUE)
+                        .count();
+                    emit(ESCAPE << 60 | run as u64)?;
+                    for v in &src[i + k..i + k + run] {
+                        emit(v.to_u64())?;
+                    }
+                    k += run;
+                } else {
+                    k += encode_block(&window[k..], self, &mut emit).map_err(|e| e.at(i + k))?;
+                }
+            }
+            i += k;
+        }
+        Ok(())
+    }
+
+    fn encode_words<F>(&self, src: &[u64], mut emit: F) -> Result<(), Error>
+    where
+        F: FnMut(u64) -> Result<(), Error>,
+    {
+        if self.strategy == EncodeStrategy::Optimal {
+            return encode_optimal(src, self, emit);
+        }
+        let mut i = 0;
+        while i < src.len() {
+            if let Some(sel) = tail_selector(&src[i..], self) {
+                return emit(pack_word(sel, &src[i..], self));
+            }
+            i += encode_block(&src[i..], self, &mut emit).map_err(|e| e.at(i))?;
+        }
+        Ok(())
+    }
 
-#[allow(dead_code)]
-#[derive(Copy, Clone)]
+    /// Unpacks every word of `src`.
+    pub fn decode_all<T: Simple8bInt>(&self, src: &[u64]) -> Result<Vec<T>, Error> {
+        let mut total = 0;
+        let mut i = 0;
+        while i < src.len() {
+            let (n, words) = block_len(&src[i..], self)?;
+            total += n;
+            i += words;
+        }
+        let mut dst = vec![T::default(); total];
+        let n = self.decode_all_into(src, &mut dst)?;
+        dst.truncate(n);
+        Ok(dst)
+    }
+
+    /// Unpacks the first `count` values of `src`, the number the encoder was
+    /// given.
+    ///
+    /// Anything packed past them, such as `tail` padding, is dropped, and
+    /// input that ends before them fails with `CountMismatch`.
+    pub fn decode_all_n<T: Simple8bInt>(&self, src: &[u64], count: usize) -> Result<Vec<T>, Error> {
+        let mut total = 0;
+        let mut last = 0;
+        let mut i = 0;
+        while total < count {
+            if i == src.len() {
+                return Err(Error::CountMismatch {
+                    expected: count as u64,
+                    actual: total as u64,
+                });
+            }
+            let (n, words) = block_len(&src[i..], self)?;
+            last = i;
+            total += n;
+            i += words;
+        }
+        if total == count {
+            last = i;
+        }
+
+        let mut dst = vec![T::default(); count];
+        let j = self.decode_all_into(&src[..last], &mut dst)?;
+        if j < count {
+            // The final block runs past `count`; keep only its first values.
+            let mut buf = [0; BUF_SIZE];
+            let values = if self.is_escape(src[last]) {
+                &src[last + 1..i]
+            } else {
+                let n = decode(&mut buf, src[last], self);
+                &buf[..n]
+            };
+            narrow(&mut dst[j..], &values[..count - j], j)?;
+        }
+        Ok(dst)
+    }
+
+    /// Unpacks every word of `src` into `dst` and returns the number of values written.
+    ///
+    /// Values that do not fit in `T` are reported as `ValueTooLarge`.
+    pub fn decode_all_into<T: Simple8bInt>(
+        &self,
+        src: &[u64],
+        dst: &mut [T],
+    ) -> Result<usize, Error> {
+        if let Some(dst) = T::as_u64_slice_mut(dst) {
+            return self.decode_words_into(src, dst);
+        }
+
+        // Narrow one block at a time; no table packs more than BUF_SIZE values.
+        let mut buf = [0; BUF_SIZE];
+        let mut i = 0;
+        let mut j = 0;
+        while i < src.len() {
+            let (n, words) = block_len(&src[i..], self)?;
+            if j + n > dst.len() {
+                return Err(Error::OutputTooSmall);
+            }
+            let values = if self.is_escape(src[i]) {
+                &src[i + 1..i + words]
+            } else {
+                decode(&mut buf, src[i], self);
+                &buf[..n]
+            };
+            narrow(&mut dst[j..j + n], values, j)?;
+            i += words;
+            j += n;
+        }
+        Ok(j)
+    }
+
+    fn decode_words_into(&self, src: &[u64], dst: &mut [u64]) -> Result<usize, Error> {
+        let kernels = simd::unpackers();
+        let mut i = 0;
+        let mut j = 0;
+        while i < src.len() {
+            let (n, words) = block_len(&src[i..], self)?;
+            if j + n > dst.len() {
+                return Err(Error::OutputTooSmall);
+            }
+            let sel = (src[i] >> 60) as usize;
+            if self.is_escape(src[i]) {
+                dst[j..j + n].copy_from_slice(&src[i + 1..i + words]);
+            } else if sel < 2 || !self.table.fast {
+                // Run words may carry a value instead of ones.
+                decode(&mut dst[j..], src[i], self);
+            } else {
+                // Safety: `unpackers` only returns kernels the CPU supports,
+                // and `dst` has room for the `n` values checked above.
+                unsafe { kernels[sel](src[i], &mut dst[j..]) };
+            }
+            i += words;
+            j += n;
+        }
+        Ok(j)
+    }
+
+    pub(crate) fn is_escape(&self, v: u64) -> bool {
+        self.escape && v >> 60 == ESCAPE
+    }
+}
+
+/// Integer types the slice API can pack directly.
+///
+/// Unsigned types are widened to `u64`; signed types are zigzag mapped so
+/// small negative values stay small. Conversions happen a block at a time,
+/// so no full-length `u64` copy of the input or output is made, except by
+/// `EncodeStrategy::Optimal`.
+pub trait Simple8bInt: Copy + Default {
+    fn to_u64(self) -> u64;
+
+    /// Returns `None` if `v` does not fit in `Self`.
+    fn from_u64(v: u64) -> Option<Self>;
+
+    // Lets `u64` skip the conversion copies.
+    #[doc(hidden)]
+    fn as_u64_slice(_src: &[Self]) -> Option<&[u64]> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn as_u64_slice_mut(_dst: &mut [Self]) -> Option<&mut [u64]> {
+        None
+    }
+}
+
+impl Simple8bInt for u64 {
+    fn to_u64(self) -> u64 {
+        self
+    }
+
+    fn from_u64(v: u64) -> Option<Self> {
+        Some(v)
+    }
+
+    fn as_u64_slice(src: &[u64]) -> Option<&[u64]> {
+        Some(src)
+    }
+
+    fn as_u64_slice_mut(dst: &mut [u64]) -> Option<&mut [u64]> {
+        Some(dst)
+    }
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {$(
+        impl Simple8bInt for $t {
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_u64(v: u64) -> Option<Self> {
+                Self::try_from(v).ok()
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {$(
+        impl Simple8bInt for $t {
+            fn to_u64(self) -> u64 {
+                zigzag_encode(self as i64)
+            }
+
+            fn from_u64(v: u64) -> Option<Self> {
+                Self::try_from(zigzag_decode(v)).ok()
+            }
+        }
+    )*};
+}
+
+impl_unsigned!(u8, u16, u32, usize);
+impl_signed!(i8, i16, i32, i64, isize);
+
+// Narrows `src` into `dst`, reporting indexes from `offset` on.
+fn narrow<T: Simple8bInt>(dst: &mut [T], src: &[u64], offset: usize) -> Result<(), Error> {
+    for (k, (d, &value)) in dst.iter_mut().zip(src).enumerate() {
+        *d = T::from_u64(value).ok_or(Error::ValueTooLarge {
+            index: offset + k,
+            value,
+        })?;
+    }
+    Ok(())
+}
+
+/// Streaming encoder.
+///
+/// Values are buffered until enough of them are available to decide on a
+/// word, which is then packed and appended to the output. `finish` packs
+/// whatever is still buffered.
+///
+/// Takes `u64` values only; convert other types with `Simple8bInt::to_u64`.
+#[derive(Clone)]
 pub struct Encoder {
-    pub buf: [u64; BUF_SIZE],
-    pub h: usize,
-    pub t: usize,
-    pub bp: usize,
-    pub bytes: [u8; BUF_SIZE * 8],
-    pub b: [u8; 8],
+    buf: [u64; BUF_SIZE],
+    h: usize,
+    t: usize,
+    n: usize,
+    bytes: Vec<u8>,
+    opts: Options,
 }
 
-#[allow(dead_code)]
 impl Encoder {
     pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(opts: Options) -> Self {
         Encoder {
             buf: [0; BUF_SIZE],
             h: 0,
             t: 0,
-            bp: 0,
-            bytes: [0; BUF_SIZE * 8],
-            b: [0; 8],
+            n: 0,
+            bytes: Vec::new(),
+            opts,
         }
     }
 
-    pub fn set_values(&mut self, v: [u64; BUF_SIZE]) {
-        self.buf = v;
-        self.t = self.buf.len();
-        self.h = 0;
-        self.bytes = [0; BUF_SIZE * 8]
+    /// Resets the encoder and writes every value of `v`.
+    pub fn set_values(&mut self, v: &[u64]) -> Result<(), Error> {
+        self.reset();
+        for &x in v {
+            self.write(x)?;
+        }
+        Ok(())
     }
 
     pub fn reset(&mut self) {
         self.h = 0;
         self.t = 0;
-        self.bp = 0;
-
-        self.buf = [0u64; BUF_SIZE];
-        self.bytes = [0u8; BUF_SIZE * 8];
-        self.b = [0u8; 8];
+        self.n = 0;
+        self.bytes.clear();
     }
 
-    pub fn write(&mut self, v: u64) {
-        if self.t >= self.buf.len() {
-            return;
+    /// Buffers `v`, packing a word as soon as a full window is buffered.
+    pub fn write(&mut self, v: u64) -> Result<(), Error> {
+        if v > MAX_VALUE && !self.opts.escape {
+            return Err(Error::ValueTooLarge {
+                index: self.n,
+                value: v,
+            });
         }
 
         if self.t >= self.buf.len() {
-            self.buf = shift(self.buf, self.h);
+            self.buf.copy_within(self.h..self.t, 0);
             self.t -= self.h;
             self.h = 0;
         }
         self.buf[self.t] = v;
         self.t += 1;
+        self.n += 1;
+
+        // The densest selector looks at most BUF_SIZE values ahead, so once
+        // that many are buffered the next word is final.
+        while self.t - self.h >= BUF_SIZE {
+            self.flush();
+        }
+        Ok(())
     }
 
+    /// Packs one block from the buffered values, if there are any.
     pub fn flush(&mut self) {
-        if self.t == 0 {
+        if self.h == self.t {
             return;
         }
-        let mut ht: [u64; BUF_SIZE] = [0; BUF_SIZE];
-        for i in 0..(self.t - self.h) {
-            ht[i] = self.buf[self.h + i]
-        }
-        let (encoded, n) = encode(ht);
-        BigEndian::write_u64(&mut self.b, encoded);
+        // Values are range checked on write, so packing cannot fail here.
+        let endian = self.opts.endian;
+        let n = encode_block(&self.buf[self.h..self.t], &self.opts, |v| {
+            self.bytes.extend_from_slice(&endian.bytes(v));
+            Ok(())
+        })
+        .expect("buffered values are valid");
 
-        if self.bp + 8 < self.bytes.len() {
-            array_append(&mut self.bytes, self.b, self.bp);
-            self.bp += 8
-        }
         self.h += n;
         if self.h == self.t {
             self.h = 0;
             self.t = 0;
         }
+    }
 
-        return ();
+    /// Packs all buffered values and returns the encoded bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush_all();
+        self.bytes
     }
 
+    /// Returns the number of values written since the last reset.
+    ///
+    /// Store it alongside the output and pass it to `Decoder::limit` to get
+    /// back exactly the values written.
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Returns the words packed so far.
     pub fn bytes(&self) -> &[u8] {
-        return &self.bytes[..self.bp];
+        &self.bytes
+    }
+
+    pub(crate) fn flush_all(&mut self) {
+        while self.h < self.t {
+            let src = &self.buf[self.h..self.t];
+            if let Some(sel) = tail_selector(src, &self.opts) {
+                let v = pack_word(sel, src, &self.opts);
+                self.bytes.extend_from_slice(&self.opts.endian.bytes(v));
+                self.h = 0;
+                self.t = 0;
+                return;
+            }
+            self.flush();
+        }
+    }
+
+    // Drops the packed words once a caller has taken them from `bytes`.
+    pub(crate) fn clear_bytes(&mut self) {
+        self.bytes.clear();
     }
 }
 
-#[allow(dead_code)]
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decoder over a stream of packed words in `Options::endian` byte order.
+///
+/// Yields one `Result` per value. Decoding stops after the first error.
+///
+/// Yields `u64` values only; narrow them with `Simple8bInt::from_u64`.
+#[derive(Clone)]
 pub struct Decoder<'a> {
-    pub bytes: &'a [u8],
-    pub buf: [u64; BUF_SIZE],
-    pub i: usize,
-    pub n: usize,
+    inner: Stream<Input<'a>, BUF_SIZE>,
 }
 
-#[allow(dead_code)]
+/// Iterator returned by `Decoder::trusted`.
+pub type TrustedDecoder<'a> = stream::TrustedDecoder<Decoder<'a>>;
+
 impl<'a> Decoder<'a> {
     pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_options(bytes, Options::default())
+    }
+
+    pub fn with_options(bytes: &'a [u8], opts: Options) -> Self {
         Decoder {
-            bytes: bytes,
-            buf: [0; BUF_SIZE],
-            i: 0,
-            n: 0,
+            inner: Stream::new(Input {
+                bytes,
+                raw: 0,
+                opts,
+            }),
         }
     }
 
-    // Error due to the fix array
-    pub fn next(&mut self) -> bool {
-        self.i += 1;
+    /// Stops after exactly `count` values, the number `Encoder::len` reported.
+    ///
+    /// Anything packed past them is never yielded, and input that ends
+    /// before them fails with `CountMismatch`.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.inner = self.inner.limit(count);
+        self
+    }
 
-        if self.i >= self.n {
-            self.read_and_move();
-        }
+    /// Restarts decoding over `bytes`, keeping any limit.
+    pub fn set_bytes(&mut self, bytes: &'a [u8]) {
+        self.inner.src.bytes = bytes;
+        self.inner.src.raw = 0;
+        self.inner.restart();
+    }
 
-        self.i < self.n
+    /// Converts into an iterator of plain values for input known to be valid.
+    pub fn trusted(self) -> TrustedDecoder<'a> {
+        TrustedDecoder::new(self)
     }
 
-    fn set_bytes(&mut self, bytes: &'a [u8]) {
-        self.bytes = bytes;
-        self.i = 0;
-        self.n = 0;
+    /// Skips up to `n` values and returns how many were skipped, which is
+    /// less than `n` only at the end of input. Named so it is not shadowed by
+    /// `Iterator::skip`.
+    ///
+    /// Whole words are hopped over by their selector; only the word holding
+    /// the next value to yield is unpacked. Decoding stops after an error,
+    /// as with `next`.
+    pub fn skip_values(&mut self, n: usize) -> Result<usize, Error> {
+        self.inner.skip_values(n)
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
     }
 
-    fn read(&self) -> u64 {
-        return self.buf[self.i];
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n)
     }
 
-    fn read_and_move(&mut self) {
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+// Input of a `Decoder`.
+#[derive(Clone)]
+struct Input<'a> {
+    bytes: &'a [u8],
+    // Raw words still owed by the current escape block.
+    raw: usize,
+    opts: Options,
+}
+
+impl Source for Input<'_> {
+    type Value = u64;
+
+    fn read(&mut self, buf: &mut [u64]) -> Result<usize, Error> {
+        loop {
+            if self.bytes.is_empty() {
+                if self.raw > 0 {
+                    return Err(Error::UnexpectedEnd);
+                }
+                return Ok(0);
+            }
+            if self.bytes.len() < 8 {
+                return Err(Error::TruncatedInput {
+                    remaining: self.bytes.len(),
+                });
+            }
+
+            let v = self.opts.endian.read(&self.bytes[..8]);
+            self.bytes = &self.bytes[8..];
+            if self.raw > 0 {
+                self.raw -= 1;
+                buf[0] = v;
+                return Ok(1);
+            } else if self.opts.is_escape(v) {
+                self.raw = (v & MAX_VALUE) as usize;
+            } else {
+                return Ok(decode(buf, v, &self.opts));
+            }
+        }
+    }
+
+    fn hop(&mut self, max: usize) -> Option<usize> {
         if self.bytes.len() < 8 {
-            return
+            return None;
+        }
+        if self.raw > 0 {
+            let k = max.min(self.raw).min(self.bytes.len() / 8);
+            self.bytes = &self.bytes[8 * k..];
+            self.raw -= k;
+            return Some(k);
+        }
+        let v = self.opts.endian.read(&self.bytes[..8]);
+        if self.opts.is_escape(v) {
+            return None;
+        }
+        let c = count(v, &self.opts);
+        if c > max {
+            return None;
         }
-    
-        let v = BigEndian::read_u64(&self.bytes[..8]);
         self.bytes = &self.bytes[8..];
-        self.n = decode(&mut self.buf, v);
-        self.i = 0;
+        Some(c)
+    }
+
+    fn remaining(&self) -> (usize, bool) {
+        let mut n = 0;
+        let mut raw = self.raw;
+        let mut words = self.bytes.chunks_exact(8);
+        for w in words.by_ref() {
+            let v = self.opts.endian.read(w);
+            if raw > 0 {
+                raw -= 1;
+                n += 1;
+            } else if self.opts.is_escape(v) {
+                raw = (v & MAX_VALUE) as usize;
+            } else {
+                n += count(v, &self.opts);
+            }
+        }
+        (n, raw > 0 || !words.remainder().is_empty())
+    }
+
+    fn clear(&mut self) {
+        self.bytes = &[];
+        self.raw = 0;
+    }
+}
+
+/// Decoder that unpacks words in place into a caller buffer.
+///
+/// Unlike `Decoder` it never copies its input: it reads straight from a
+/// `&[u64]`, or from an aligned byte buffer such as a memory-mapped file.
+#[derive(Clone)]
+pub struct WordsDecoder<'a> {
+    words: &'a [u64],
+    // Raw words still owed by the current escape block.
+    raw: usize,
+    // Whether words are stored in the opposite of native byte order.
+    swap: bool,
+    opts: Options,
+    // Values to decode in total, if known, and values decoded so far.
+    limit: Option<usize>,
+    taken: usize,
+}
+
+impl<'a> WordsDecoder<'a> {
+    pub fn new(words: &'a [u64]) -> Self {
+        Self::with_options(words, Options::default())
+    }
+
+    /// `opts.endian` is ignored: the words are already `u64`s.
+    pub fn with_options(words: &'a [u64], opts: Options) -> Self {
+        WordsDecoder {
+            words,
+            raw: 0,
+            swap: false,
+            opts,
+            limit: None,
+            taken: 0,
+        }
+    }
+
+    /// Views `bytes`, stored in `opts.endian` byte order, as words.
+    ///
+    /// `bytes` must start on an 8-byte boundary, which holds for memory maps
+    /// and `Vec<u64>` allocations.
+    pub fn from_bytes(bytes: &'a [u8], opts: Options) -> Result<Self, Error> {
+        let (words, swap) = aligned_words(bytes, opts.endian)?;
+        Ok(WordsDecoder {
+            swap,
+            ..Self::with_options(words, opts)
+        })
+    }
+
+    /// Stops after exactly `count` values, like `Decoder::limit`.
+    ///
+    /// Anything packed past them is dropped, and input that ends before them
+    /// fails with `CountMismatch`.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Returns true once every word has been decoded.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Decodes as many whole words as fit into `dst` and returns the number
+    /// of values written, which is 0 at the end of input.
+    ///
+    /// A `dst` of at least 240 values always makes progress; a smaller one
+    /// fails with `OutputTooSmall` if the next word does not fit. Raw values
+    /// of an escape block may be split across calls. Decoding stops after
+    /// the first error.
+    pub fn decode_into(&mut self, dst: &mut [u64]) -> Result<usize, Error> {
+        let r = self.fill_limited(dst);
+        if r.is_err() {
+            self.words = &[];
+            self.raw = 0;
+        }
+        r
+    }
+
+    fn fill_limited(&mut self, dst: &mut [u64]) -> Result<usize, Error> {
+        let Some(limit) = self.limit else {
+            return self.fill(dst);
+        };
+        let left = limit - self.taken;
+        if left == 0 {
+            return Ok(0);
+        }
+        let cap = left.min(dst.len());
+        let n = self.fill(&mut dst[..cap])?;
+        if n == 0 && !dst.is_empty() {
+            return Err(Error::CountMismatch {
+                expected: limit as u64,
+                actual: self.taken as u64,
+            });
+        }
+        self.taken += n;
+        if self.taken == limit {
+            self.words = &[];
+            self.raw = 0;
+        }
+        Ok(n)
+    }
+
+    fn fill(&mut self, dst: &mut [u64]) -> Result<usize, Error> {
+        let kernels = simd::unpackers();
+        let mut j = 0;
+        loop {
+            if self.raw > 0 {
+                if self.words.is_empty() {
+                    return Err(Error::UnexpectedEnd);
+                }
+                let k = self.raw.min(self.words.len()).min(dst.len() - j);
+                if k == 0 {
+                    return Ok(j);
+                }
+                for (d, &w) in dst[j..j + k].iter_mut().zip(self.words) {
+                    *d = self.word(w);
+                }
+                self.words = &self.words[k..];
+                self.raw -= k;
+                j += k;
+                continue;
+            }
+
+            let v = match self.words.first() {
+                Some(&w) => self.word(w),
+                None => return Ok(j),
+            };
+            if self.opts.is_escape(v) {
+                self.raw = (v & MAX_VALUE) as usize;
+                self.words = &self.words[1..];
+                continue;
+            }
+            let n = count(v, &self.opts);
+            if j + n > dst.len() {
+                // The word holding the last value may run past the limit.
+                if self.limit == Some(self.taken + dst.len()) {
+                    let mut buf = [0; BUF_SIZE];
+                    decode(&mut buf, v, &self.opts);
+                    let k = dst.len() - j;
+                    dst[j..].copy_from_slice(&buf[..k]);
+                    self.words = &self.words[1..];
+                    return Ok(dst.len());
+                }
+                if j == 0 {
+                    return Err(Error::OutputTooSmall);
+                }
+                return Ok(j);
+            }
+            let sel = (v >> 60) as usize;
+            if sel < 2 || !self.opts.table.fast {
+                decode(&mut dst[j..], v, &self.opts);
+            } else {
+                // Safety: `unpackers` only returns kernels the CPU supports,
+                // and `dst` has room for the `n` values checked above.
+                unsafe { kernels[sel](v, &mut dst[j..]) };
+            }
+            self.words = &self.words[1..];
+            j += n;
+        }
+    }
+
+    fn word(&self, w: u64) -> u64 {
+        if self.swap {
+            w.swap_bytes()
+        } else {
+            w
+        }
     }
 }
 
-#[allow(dead_code)]
 struct Packing {
     n: usize,
     bit: usize,
@@ -148,763 +1086,1047 @@ struct Packing {
     pack: fn(&[u64]) -> u64,
 }
 
-#[allow(dead_code)]
+macro_rules! packing {
+    ($n:literal, 0) => {
+        Packing {
+            n: $n,
+            bit: 0,
+            unpack: unpack_run::<$n>,
+            pack: pack_run,
+        }
+    };
+    ($n:literal, $bit:literal) => {
+        Packing {
+            n: $n,
+            bit: $bit,
+            unpack: unpack::<$n, $bit>,
+            pack: pack::<$n, $bit>,
+        }
+    };
+}
+
 const SELECTOR: [Packing; 16] = [
-    Packing {
-        n: 240,
-        bit: 0,
-        unpack: unpack240,
-        pack: pack240,
-    },
-    Packing {
-        n: 120,
-        bit: 0,
-        unpack: unpack120,
-        pack: pack120,
-    },
-    Packing {
-        n: 60,
-        bit: 1,
-        unpack: unpack60,
-        pack: pack60,
-    },
-    Packing {
-        n: 30,
-        bit: 2,
-        unpack: unpack30,
-        pack: pack30,
-    },
-    Packing {
-        n: 20,
-        bit: 3,
-        unpack: unpack20,
-        pack: pack20,
-    },
-    Packing {
-        n: 15,
-        bit: 4,
-        unpack: unpack15,
-        pack: pack15,
-    },
-    Packing {
-        n: 12,
-        bit: 5,
-        unpack: unpack12,
-        pack: pack12,
-    },
-    Packing {
-        n: 10,
-        bit: 6,
-        unpack: unpack10,
-        pack: pack10,
-    },
-    Packing {
-        n: 8,
-        bit: 7,
-        unpack: unpack8,
-        pack: pack8,
-    },
-    Packing {
-        n: 7,
-        bit: 8,
-        unpack: unpack7,
-        pack: pack7,
-    },
-    Packing {
-        n: 6,
-        bit: 10,
-        unpack: unpack6,
-        pack: pack6,
-    },
-    Packing {
-        n: 5,
-        bit: 12,
-        unpack: unpack5,
-        pack: pack5,
-    },
-    Packing {
-        n: 4,
-        bit: 15,
-        unpack: unpack4,
-        pack: pack4,
-    },
-    Packing {
-        n: 3,
-        bit: 20,
-        unpack: unpack3,
-        pack: pack3,
-    },
-    Packing {
-        n: 2,
-        bit: 30,
-        unpack: unpack2,
-        pack: pack2,
-    },
-    Packing {
-        n: 1,
-        bit: 60,
-        unpack: unpack1,
-        pack: pack1,
-    },
+    packing!(240, 0),
+    packing!(120, 0),
+    packing!(60, 1),
+    packing!(30, 2),
+    packing!(20, 3),
+    packing!(15, 4),
+    packing!(12, 5),
+    packing!(10, 6),
+    packing!(8, 7),
+    packing!(7, 8),
+    packing!(6, 10),
+    packing!(5, 12),
+    packing!(4, 15),
+    packing!(3, 20),
+    packing!(2, 30),
+    packing!(1, 60),
 ];
 
-fn count(v: u64) -> usize {
-    let sel = v >> 60;
-    if sel >= 16 {
-        return 0;
-    }
-    return SELECTOR[sel as usize].n;
+// Every 4-bit selector has a packing, so neither of these can fail.
+fn count(v: u64, opts: &Options) -> usize {
+    opts.table.entry((v >> 60) as usize).0
 }
 
-#[allow(dead_code)]
-fn decode(dst: &mut [u64], v: u64) -> usize {
-    let sel = v >> 60;
-    if sel >= 16 {
-        return 0;
+pub(crate) fn decode(dst: &mut [u64], v: u64, opts: &Options) -> usize {
+    let sel = (v >> 60) as usize;
+    let (n, bits) = opts.table.entry(sel);
+    if bits == 0 {
+        let run = if opts.run_values {
+            v & MAX_VALUE
+        } else {
+            opts.table.run
+        };
+        dst[..n].fill(run);
+    } else if opts.table.fast {
+        (SELECTOR[sel].unpack)(v, dst);
+    } else {
+        let mask = (1u64 << bits) - 1;
+        for (i, d) in dst[..n].iter_mut().enumerate() {
+            *d = (v >> (i * bits)) & mask;
+        }
     }
-    let unpack = SELECTOR[sel as usize].unpack;
-    unpack(v, dst);
-    return SELECTOR[sel as usize].n;
+    n
+}
+
+// Returns the number of values and words in the block at the start of `src`.
+pub(crate) fn block_len(src: &[u64], opts: &Options) -> Result<(usize, usize), Error> {
+    header_len(src[0], src.len() - 1, opts)
 }
 
-#[allow(dead_code)]
-fn decode_all(dst: &mut [u64], src: [u64; BUF_SIZE]) -> usize {
-    let mut j = 0;
-    for i in 0..src.len() {
-        let v = src[i];
-        let sel = v >> 60;
-        if sel >= 16 {
-            return 0;
+// Like `block_len`, for a first word `v` followed by `avail` more words.
+pub(crate) fn header_len(v: u64, avail: usize, opts: &Options) -> Result<(usize, usize), Error> {
+    if opts.is_escape(v) {
+        let k = (v & MAX_VALUE) as usize;
+        if avail < k {
+            return Err(Error::UnexpectedEnd);
         }
-        let unpack = SELECTOR[sel as usize].unpack;
-        unpack(v, &mut dst[j..]);
-        j += SELECTOR[sel as usize].n;
+        return Ok((k, 1 + k));
     }
-    return j;
+    Ok((count(v, opts), 1))
 }
 
-fn array_append(src: &mut [u8; BUF_SIZE * 8], b: [u8; 8], index: usize) {
-    for i in index..(index + 8) {
-        src[i] = b[i - index]
+// Views `bytes` as words and reports whether `endian` needs them swapped.
+pub(crate) fn aligned_words(bytes: &[u8], endian: Endian) -> Result<(&[u64], bool), Error> {
+    // Safety: every bit pattern is a valid u64.
+    let (head, words, tail) = unsafe { bytes.align_to::<u64>() };
+    if !head.is_empty() {
+        return Err(Error::Misaligned);
     }
+    if !tail.is_empty() {
+        return Err(Error::TruncatedInput {
+            remaining: tail.len(),
+        });
+    }
+    let native = if cfg!(target_endian = "big") {
+        Endian::Big
+    } else {
+        Endian::Little
+    };
+    Ok((words, endian != native))
+}
+
+/// Packs `src` into simple8b words.
+///
+/// Any number of values is accepted; every value must be at most `MAX_VALUE`
+/// once widened to `u64`.
+pub fn encode_all<T: Simple8bInt>(src: &[T]) -> Result<Vec<u64>, Error> {
+    Options::default().encode_all(src)
+}
+
+/// Packs `src` into `dst` and returns the number of words written.
+pub fn encode_all_into<T: Simple8bInt>(src: &[T], dst: &mut [u64]) -> Result<usize, Error> {
+    Options::default().encode_all_into(src, dst)
+}
+
+/// Unpacks every word of `src`.
+pub fn decode_all<T: Simple8bInt>(src: &[u64]) -> Result<Vec<T>, Error> {
+    Options::default().decode_all(src)
+}
+
+/// Unpacks every word of `src` into `dst` and returns the number of values written.
+pub fn decode_all_into<T: Simple8bInt>(src: &[u64], dst: &mut [T]) -> Result<usize, Error> {
+    Options::default().decode_all_into(src, dst)
+}
+
+fn can_pack_run(src: &[u64], n: usize, run: u64) -> bool {
+    src.len() >= n && src[..n].iter().all(|&v| v == run)
 }
 
-fn shift(mut array: [u64; BUF_SIZE], index: usize) -> [u64; BUF_SIZE] {
-    for i in index..4 {
-        array[i - index] = array[i]
+// Packs as many leading values of `src` as fit in one word; `src` must not be
+// empty.
+fn encode(src: &[u64], opts: &Options) -> Result<(u64, usize), Error> {
+    if !opts.table.fast {
+        return encode_scalar(src, opts);
+    }
+    for (sel, p) in SELECTOR[..2].iter().enumerate() {
+        if opts.escape && sel as u64 == ESCAPE {
+            continue;
+        }
+        let fits = if opts.run_values {
+            src[0] <= MAX_VALUE && can_pack_run(src, p.n, src[0])
+        } else {
+            can_pack_run(src, p.n, opts.table.run)
+        };
+        if fits {
+            return Ok((pack_word(sel, src, opts), p.n));
+        }
+    }
+
+    match select(src) {
+        Some(sel) => Ok((pack_word(sel, src, opts), SELECTOR[sel].n)),
+        None => Err(Error::ValueTooLarge {
+            index: 0,
+            value: src[0],
+        }),
     }
-    array
 }
 
-fn can_pack(src: &[u64], n: usize, bits: usize) -> bool {
-    if src.len() < n {
-        return false;
+// Returns the packed selector holding the longest prefix of `src`, or `None`
+// if `src[0]` is wider than 60 bits. Walks the packed selectors from the
+// fewest values up, OR-ing in only the values each one adds. Counts grow as
+// widths shrink, so the first selector that overflows ends the search.
+fn select(src: &[u64]) -> Option<usize> {
+    let mut best = None;
+    let mut acc = 0;
+    let mut start = 0;
+    for sel in (2..SELECTOR.len()).rev() {
+        let p = &SELECTOR[sel];
+        if p.n > src.len() {
+            break;
+        }
+        acc |= src[start..p.n].iter().fold(0, |acc, &v| acc | v);
+        start = p.n;
+        if acc >> p.bit != 0 {
+            break;
+        }
+        best = Some(sel);
     }
+    best
+}
 
-    let mut end = src.len();
-    if n < end {
-        end = n;
+// `encode` for tables without kernels: takes the selector that fits the
+// most values.
+fn encode_scalar(src: &[u64], opts: &Options) -> Result<(u64, usize), Error> {
+    let best = (0..16)
+        .filter(|&sel| !(opts.escape && sel as u64 == ESCAPE))
+        .filter_map(|sel| {
+            let (n, bits) = opts.table.entry(sel);
+            if n > src.len() {
+                return None;
+            }
+            let fits = if bits == 0 {
+                let run = if opts.run_values {
+                    src[0]
+                } else {
+                    opts.table.run
+                };
+                run <= MAX_VALUE && can_pack_run(src, n, run)
+            } else {
+                src[..n].iter().all(|&v| v >> bits == 0)
+            };
+            fits.then_some((sel, n))
+        })
+        .max_by_key(|&(_, n)| n);
+    match best {
+        Some((sel, n)) => Ok((pack_word(sel, src, opts), n)),
+        None => Err(Error::ValueTooLarge {
+            index: 0,
+            value: src[0],
+        }),
     }
+}
 
-    if bits == 0 {
-        for v in src {
-            if *v != 1 {
+// A `src` shorter than the packing, as left by `tail_selector`, is padded
+// with zeros.
+fn pack_word(sel: usize, src: &[u64], opts: &Options) -> u64 {
+    let p = &SELECTOR[sel];
+    let (n, bits) = opts.table.entry(sel);
+    let payload = if bits == 0 {
+        // The repeated value rides in the payload bits.
+        if opts.run_values {
+            src[0]
+        } else {
+            0
+        }
+    } else if !opts.table.fast {
+        let packed = src.iter().take(n).enumerate();
+        packed.fold(0, |v, (i, &x)| v | x << (i * bits))
+    } else if src.len() < p.n {
+        let mut padded = [0; 60];
+        padded[..src.len()].copy_from_slice(src);
+        (p.pack)(&padded)
+    } else {
+        (p.pack)(src)
+    };
+    (sel as u64) << 60 | payload
+}
+
+// With `tail` set, returns the selector that packs all of `src` into one word
+// with the least padding, or `None` if no single word holds it. Only called on
+// the last values of a stream.
+fn tail_selector(src: &[u64], opts: &Options) -> Option<usize> {
+    if !opts.tail || src.is_empty() || src.len() > BUF_SIZE {
+        return None;
+    }
+    let acc = src.iter().fold(0, |acc, &v| acc | v);
+    // The fit with the fewest values pads the least.
+    (0..16)
+        .filter(|&sel| {
+            let (n, bits) = opts.table.entry(sel);
+            if n < src.len() || (opts.escape && sel as u64 == ESCAPE) {
                 return false;
             }
+            if bits == 0 {
+                let run = if opts.run_values {
+                    src[0]
+                } else {
+                    opts.table.run
+                };
+                run <= MAX_VALUE && src.iter().all(|&v| v == run)
+            } else {
+                acc >> bits == 0
+            }
+        })
+        .min_by_key(|&sel| opts.table.entry(sel).0)
+}
+
+// Packs the block at the start of `src`, handing each word to `emit`, and
+// returns the number of values consumed. A block is a single word, or with
+// `escape` set, an escape word followed by a run of raw values.
+fn encode_block<F>(src: &[u64], opts: &Options, mut emit: F) -> Result<usize, Error>
+where
+    F: FnMut(u64) -> Result<(), Error>,
+{
+    match encode(src, opts) {
+        Ok((v, n)) => {
+            emit(v)?;
+            Ok(n)
         }
-        return true;
-    }
-
-    let max = ((1 << (bits as u64)) - 1) as u64;
-    for i in 0..end {
-        if src[i] > max {
-            return false;
-        }
-    }
-    true
-}
-
-#[allow(dead_code)]
-fn pack240(src: &[u64]) -> u64 {
-    return 0;
-}
-
-#[allow(dead_code)]
-fn pack120(src: &[u64]) -> u64 {
-    return 0;
-}
-
-#[allow(dead_code)]
-fn pack60(src: &[u64]) -> u64 {
-    return 2 << 60
-        | src[0]
-        | src[1] << 1
-        | src[2] << 2
-        | src[3] << 3
-        | src[4] << 4
-        | src[5] << 5
-        | src[6] << 6
-        | src[7] << 7
-        | src[8] << 8
-        | src[9] << 9
-        | src[10] << 10
-        | src[11] << 11
-        | src[12] << 12
-        | src[13] << 13
-        | src[14] << 14
-        | src[15] << 15
-        | src[16] << 16
-        | src[17] << 17
-        | src[18] << 18
-        | src[19] << 19
-        | src[20] << 20
-        | src[21] << 21
-        | src[22] << 22
-        | src[23] << 23
-        | src[24] << 24
-        | src[25] << 25
-        | src[26] << 26
-        | src[27] << 27
-        | src[28] << 28
-        | src[29] << 29
-        | src[30] << 30
-        | src[31] << 31
-        | src[32] << 32
-        | src[33] << 33
-        | src[34] << 34
-        | src[35] << 35
-        | src[36] << 36
-        | src[37] << 37
-        | src[38] << 38
-        | src[39] << 39
-        | src[40] << 40
-        | src[41] << 41
-        | src[42] << 42
-        | src[43] << 43
-        | src[44] << 44
-        | src[45] << 45
-        | src[46] << 46
-        | src[47] << 47
-        | src[48] << 48
-        | src[49] << 49
-        | src[50] << 50
-        | src[51] << 51
-        | src[52] << 52
-        | src[53] << 53
-        | src[54] << 54
-        | src[55] << 55
-        | src[56] << 56
-        | src[57] << 57
-        | src[58] << 58
-        | src[59] << 59;
-}
-
-fn pack30(src: &[u64]) -> u64 {
-    return 3 << 60
-        | src[0]
-        | src[1] << 2
-        | src[2] << 4
-        | src[3] << 6
-        | src[4] << 8
-        | src[5] << 10
-        | src[6] << 12
-        | src[7] << 14
-        | src[8] << 16
-        | src[9] << 18
-        | src[10] << 20
-        | src[11] << 22
-        | src[12] << 24
-        | src[13] << 26
-        | src[14] << 28
-        | src[15] << 30
-        | src[16] << 32
-        | src[17] << 34
-        | src[18] << 36
-        | src[19] << 38
-        | src[20] << 40
-        | src[21] << 42
-        | src[22] << 44
-        | src[23] << 46
-        | src[24] << 48
-        | src[25] << 50
-        | src[26] << 52
-        | src[27] << 54
-        | src[28] << 56
-        | src[29] << 58;
-}
-
-fn pack20(src: &[u64]) -> u64 {
-    return 4 << 60
-        | src[0]
-        | src[1] << 3
-        | src[2] << 6
-        | src[3] << 9
-        | src[4] << 12
-        | src[5] << 15
-        | src[6] << 18
-        | src[7] << 21
-        | src[8] << 24
-        | src[9] << 27
-        | src[10] << 30
-        | src[11] << 33
-        | src[12] << 36
-        | src[13] << 39
-        | src[14] << 42
-        | src[15] << 45
-        | src[16] << 48
-        | src[17] << 51
-        | src[18] << 54
-        | src[19] << 57;
-}
-
-fn pack15(src: &[u64]) -> u64 {
-    return 5 << 60
-        | src[0]
-        | src[1] << 4
-        | src[2] << 8
-        | src[3] << 12
-        | src[4] << 16
-        | src[5] << 20
-        | src[6] << 24
-        | src[7] << 28
-        | src[8] << 32
-        | src[9] << 36
-        | src[10] << 40
-        | src[11] << 44
-        | src[12] << 48
-        | src[13] << 52
-        | src[14] << 56;
-}
-
-fn pack12(src: &[u64]) -> u64 {
-    return 6 << 60
-        | src[0]
-        | src[1] << 5
-        | src[2] << 10
-        | src[3] << 15
-        | src[4] << 20
-        | src[5] << 25
-        | src[6] << 30
-        | src[7] << 35
-        | src[8] << 40
-        | src[9] << 45
-        | src[10] << 50
-        | src[11] << 55;
-}
-
-fn pack10(src: &[u64]) -> u64 {
-    return 7 << 60
-        | src[0]
-        | src[1] << 6
-        | src[2] << 12
-        | src[3] << 18
-        | src[4] << 24
-        | src[5] << 30
-        | src[6] << 36
-        | src[7] << 42
-        | src[8] << 48
-        | src[9] << 54;
-}
-
-fn pack8(src: &[u64]) -> u64 {
-    return 8 << 60
-        | src[0]
-        | src[1] << 7
-        | src[2] << 14
-        | src[3] << 21
-        | src[4] << 28
-        | src[5] << 35
-        | src[6] << 42
-        | src[7] << 49;
-}
-
-fn pack7(src: &[u64]) -> u64 {
-    return 9 << 60
-        | src[0]
-        | src[1] << 8
-        | src[2] << 16
-        | src[3] << 24
-        | src[4] << 32
-        | src[5] << 40
-        | src[6] << 48;
-}
-
-fn pack6(src: &[u64]) -> u64 {
-    return 10 << 60
-        | src[0]
-        | src[1] << 10
-        | src[2] << 20
-        | src[3] << 30
-        | src[4] << 40
-        | src[5] << 50;
-}
-
-fn pack5(src: &[u64]) -> u64 {
-    return 11 << 60 | src[0] | src[1] << 12 | src[2] << 24 | src[3] << 36 | src[4] << 48;
-}
-
-fn pack4(src: &[u64]) -> u64 {
-    return 12 << 60 | src[0] | src[1] << 15 | src[2] << 30 | src[3] << 45;
-}
-
-fn pack3(src: &[u64]) -> u64 {
-    return 13 << 60 | src[0] | src[1] << 20 | src[2] << 40;
-}
-
-fn pack2(src: &[u64]) -> u64 {
-    return 14 << 60 | src[0] | src[1] << 30;
-}
-
-fn pack1(src: &[u64]) -> u64 {
-    return 15 << 60 | src[0];
-}
-
-fn encode(src: [u64; BUF_SIZE]) -> (u64, usize) {
-    if can_pack(&src, 240, 0) {
-        return (0, 240);
-    } else if can_pack(&src, 120, 0) {
-        return (1 << 60, 120);
-    } else if can_pack(&src, 60, 1) {
-        return (pack60(&src[..60]), 60);
-    } else if can_pack(&src, 30, 2) {
-        return (pack30(&src[..30]), 30);
-    } else if can_pack(&src, 20, 3) {
-        return (pack20(&src[..20]), 20);
-    } else if can_pack(&src, 15, 4) {
-        return (pack15(&src[..15]), 15);
-    } else if can_pack(&src, 12, 5) {
-        return (pack12(&src[..12]), 12);
-    } else if can_pack(&src, 10, 6) {
-        return (pack10(&src[..10]), 10);
-    } else if can_pack(&src, 8, 7) {
-        return (pack8(&src[..8]), 8);
-    } else if can_pack(&src, 7, 8) {
-        return (pack7(&src[..7]), 7);
-    } else if can_pack(&src, 6, 10) {
-        return (pack6(&src[..6]), 6);
-    } else if can_pack(&src, 5, 12) {
-        return (pack5(&src[..5]), 5);
-    } else if can_pack(&src, 4, 15) {
-        return (pack4(&src[..4]), 4);
-    } else if can_pack(&src, 3, 20) {
-        return (pack3(&src[..3]), 3);
-    } else if can_pack(&src, 2, 30) {
-        return (pack2(&src[..2]), 2);
-    } else if can_pack(&src, 1, 60) {
-        return (pack1(&src[..1]), 1);
-    } else {
-        if src.len() > 0 {
-            return (0, 0);
+        Err(_) if opts.escape => {
+            let k = src.iter().take_while(|&&v| v > MAX_VALUE).count();
+            emit(ESCAPE << 60 | k as u64)?;
+            for &v in &src[..k] {
+                emit(v)?;
+            }
+            Ok(k)
         }
-        return (0, 0);
+        Err(e) => Err(e),
     }
 }
 
-fn encode_all(src: [u64; BUF_SIZE]) -> [u64; BUF_SIZE] {
-    let mut i = 0;
+// Packs `src` into the fewest words. `cost[i]` is the minimum number of words
+// for `src[i..]` and `next[i]` the selector of its first word.
+fn encode_optimal<F>(src: &[u64], opts: &Options, mut emit: F) -> Result<(), Error>
+where
+    F: FnMut(u64) -> Result<(), Error>,
+{
+    if !opts.escape {
+        if let Some(index) = src.iter().position(|&v| v > MAX_VALUE) {
+            return Err(Error::ValueTooLarge {
+                index,
+                value: src[index],
+            });
+        }
+    }
 
-    // Re-use the input slice and write encoded values back in place
-    let mut dst = src;
-    let mut j = 0;
+    let len = src.len();
+    let mut cost = vec![0usize; len + 1];
+    let mut next = vec![0usize; len];
+    // Length of the run of equal values starting at each position.
+    let mut run = vec![1usize; len + 1];
 
-    loop {
-        if i >= src.len() {
-            break;
+    for i in (0..len).rev() {
+        if i + 1 < len && src[i] == src[i + 1] {
+            run[i] = run[i + 1] + 1;
         }
-        let remaining = &src[i..];
-
-        if can_pack(remaining, 240, 0) {
-            dst[j] = 0;
-            i += 240;
-        } else if can_pack(remaining, 120, 0) {
-            dst[j] = 1 << 60;
-            i += 120;
-        } else if can_pack(remaining, 60, 1) {
-            dst[j] = pack60(&src[i..i + 60]);
-            i += 60
-        } else if can_pack(remaining, 30, 2) {
-            dst[j] = pack30(&src[i..i + 30]);
-            i += 30
-        } else if can_pack(remaining, 20, 3) {
-            dst[j] = pack20(&src[i..i + 20]);
-            i += 20
-        } else if can_pack(remaining, 15, 4) {
-            dst[j] = pack15(&src[i..i + 15]);
-            i += 15
-        } else if can_pack(remaining, 12, 5) {
-            dst[j] = pack12(&src[i..i + 12]);
-            i += 12
-        } else if can_pack(remaining, 10, 6) {
-            dst[j] = pack10(&src[i..i + 10]);
-            i += 10
-        } else if can_pack(remaining, 8, 7) {
-            dst[j] = pack8(&src[i..i + 8]);
-            i += 8
-        } else if can_pack(remaining, 7, 8) {
-            dst[j] = pack7(&src[i..i + 7]);
-            i += 7
-        } else if can_pack(remaining, 6, 10) {
-            dst[j] = pack6(&src[i..i + 6]);
-            i += 6
-        } else if can_pack(remaining, 5, 12) {
-            dst[j] = pack5(&src[i..i + 5]);
-            i += 5
-        } else if can_pack(remaining, 4, 15) {
-            dst[j] = pack4(&src[i..i + 4]);
-            i += 4
-        } else if can_pack(remaining, 3, 20) {
-            dst[j] = pack3(&src[i..i + 3]);
-            i += 3
-        } else if can_pack(remaining, 2, 30) {
-            dst[j] = pack2(&src[i..i + 2]);
-            i += 2
-        } else if can_pack(remaining, 1, 60) {
-            dst[j] = pack1(&src[i..i + 1]);
-            i += 1
+
+        if src[i] > MAX_VALUE {
+            let k = src[i..].iter().take_while(|&&v| v > MAX_VALUE).count();
+            cost[i] = 1 + k + cost[i + k];
+            continue;
+        }
+        if let Some(sel) = tail_selector(&src[i..], opts) {
+            cost[i] = 1;
+            next[i] = sel;
+            continue;
+        }
+
+        // prefix[j] is the bit width of the widest value in src[i..=i + j].
+        let mut prefix = [0usize; 60];
+        let window = (len - i).min(60);
+        let mut width = 0;
+        for (j, w) in prefix[..window].iter_mut().enumerate() {
+            width = width.max(64 - src[i + j].leading_zeros() as usize);
+            *w = width;
+        }
+
+        let mut best = usize::MAX;
+        // The presets run from dense to sparse, so ties keep the longer block.
+        for sel in 0..16 {
+            if opts.escape && sel as u64 == ESCAPE {
+                continue;
+            }
+            let (n, bits) = opts.table.entry(sel);
+            if i + n > len {
+                continue;
+            }
+            let fits = if bits == 0 {
+                let value = if opts.run_values {
+                    src[i]
+                } else {
+                    opts.table.run
+                };
+                src[i] == value && run[i] >= n
+            } else {
+                prefix[n - 1] <= bits
+            };
+            if fits && 1 + cost[i + n] < best {
+                best = 1 + cost[i + n];
+                next[i] = sel;
+            }
+        }
+        cost[i] = best;
+    }
+
+    let mut i = 0;
+    while i < len {
+        if src[i] > MAX_VALUE {
+            let k = src[i..].iter().take_while(|&&v| v > MAX_VALUE).count();
+            emit(ESCAPE << 60 | k as u64)?;
+            for &v in &src[i..i + k] {
+                emit(v)?;
+            }
+            i += k;
         } else {
-            return [0; BUF_SIZE];
-        }
-        j += 1
-    }
-    return dst;
-}
-
-#[allow(dead_code)]
-fn unpack240(v: u64, dst: &mut [u64]) {
-    for i in 0..BUF_SIZE {
-        dst[i] = 1
-    }
-}
-
-#[allow(dead_code)]
-fn unpack120(v: u64, dst: &mut [u64]) {
-    for i in 0..BUF_SIZE {
-        dst[i] = 1
-    }
-}
-
-#[allow(dead_code)]
-fn unpack60(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 1;
-    dst[1] = (v >> 1) & 1;
-    dst[2] = (v >> 2) & 1;
-    dst[3] = (v >> 3) & 1;
-    dst[4] = (v >> 4) & 1;
-    dst[5] = (v >> 5) & 1;
-    dst[6] = (v >> 6) & 1;
-    dst[7] = (v >> 7) & 1;
-    dst[8] = (v >> 8) & 1;
-    dst[9] = (v >> 9) & 1;
-    dst[10] = (v >> 10) & 1;
-    dst[11] = (v >> 11) & 1;
-    dst[12] = (v >> 12) & 1;
-    dst[13] = (v >> 13) & 1;
-    dst[14] = (v >> 14) & 1;
-    dst[15] = (v >> 15) & 1;
-    dst[16] = (v >> 16) & 1;
-    dst[17] = (v >> 17) & 1;
-    dst[18] = (v >> 18) & 1;
-    dst[19] = (v >> 19) & 1;
-    dst[20] = (v >> 20) & 1;
-    dst[21] = (v >> 21) & 1;
-    dst[22] = (v >> 22) & 1;
-    dst[23] = (v >> 23) & 1;
-    dst[24] = (v >> 24) & 1;
-    dst[25] = (v >> 25) & 1;
-    dst[26] = (v >> 26) & 1;
-    dst[27] = (v >> 27) & 1;
-    dst[28] = (v >> 28) & 1;
-    dst[29] = (v >> 29) & 1;
-    dst[30] = (v >> 30) & 1;
-    dst[31] = (v >> 31) & 1;
-    dst[32] = (v >> 32) & 1;
-    dst[33] = (v >> 33) & 1;
-    dst[34] = (v >> 34) & 1;
-    dst[35] = (v >> 35) & 1;
-    dst[36] = (v >> 36) & 1;
-    dst[37] = (v >> 37) & 1;
-    dst[38] = (v >> 38) & 1;
-    dst[39] = (v >> 39) & 1;
-    dst[40] = (v >> 40) & 1;
-    dst[41] = (v >> 41) & 1;
-    dst[42] = (v >> 42) & 1;
-    dst[43] = (v >> 43) & 1;
-    dst[44] = (v >> 44) & 1;
-    dst[45] = (v >> 45) & 1;
-    dst[46] = (v >> 46) & 1;
-    dst[47] = (v >> 47) & 1;
-    dst[48] = (v >> 48) & 1;
-    dst[49] = (v >> 49) & 1;
-    dst[50] = (v >> 50) & 1;
-    dst[51] = (v >> 51) & 1;
-    dst[52] = (v >> 52) & 1;
-    dst[53] = (v >> 53) & 1;
-    dst[54] = (v >> 54) & 1;
-    dst[55] = (v >> 55) & 1;
-    dst[56] = (v >> 56) & 1;
-    dst[57] = (v >> 57) & 1;
-    dst[58] = (v >> 58) & 1;
-    dst[59] = (v >> 59) & 1;
-}
-
-fn unpack30(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 3;
-    dst[1] = (v >> 2) & 3;
-    dst[2] = (v >> 4) & 3;
-    dst[3] = (v >> 6) & 3;
-    dst[4] = (v >> 8) & 3;
-    dst[5] = (v >> 10) & 3;
-    dst[6] = (v >> 12) & 3;
-    dst[7] = (v >> 14) & 3;
-    dst[8] = (v >> 16) & 3;
-    dst[9] = (v >> 18) & 3;
-    dst[10] = (v >> 20) & 3;
-    dst[11] = (v >> 22) & 3;
-    dst[12] = (v >> 24) & 3;
-    dst[13] = (v >> 26) & 3;
-    dst[14] = (v >> 28) & 3;
-    dst[15] = (v >> 30) & 3;
-    dst[16] = (v >> 32) & 3;
-    dst[17] = (v >> 34) & 3;
-    dst[18] = (v >> 36) & 3;
-    dst[19] = (v >> 38) & 3;
-    dst[20] = (v >> 40) & 3;
-    dst[21] = (v >> 42) & 3;
-    dst[22] = (v >> 44) & 3;
-    dst[23] = (v >> 46) & 3;
-    dst[24] = (v >> 48) & 3;
-    dst[25] = (v >> 50) & 3;
-    dst[26] = (v >> 52) & 3;
-    dst[27] = (v >> 54) & 3;
-    dst[28] = (v >> 56) & 3;
-    dst[29] = (v >> 58) & 3;
-}
-
-fn unpack20(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 7;
-    dst[1] = (v >> 3) & 7;
-    dst[2] = (v >> 6) & 7;
-    dst[3] = (v >> 9) & 7;
-    dst[4] = (v >> 12) & 7;
-    dst[5] = (v >> 15) & 7;
-    dst[6] = (v >> 18) & 7;
-    dst[7] = (v >> 21) & 7;
-    dst[8] = (v >> 24) & 7;
-    dst[9] = (v >> 27) & 7;
-    dst[10] = (v >> 30) & 7;
-    dst[11] = (v >> 33) & 7;
-    dst[12] = (v >> 36) & 7;
-    dst[13] = (v >> 39) & 7;
-    dst[14] = (v >> 42) & 7;
-    dst[15] = (v >> 45) & 7;
-    dst[16] = (v >> 48) & 7;
-    dst[17] = (v >> 51) & 7;
-    dst[18] = (v >> 54) & 7;
-    dst[19] = (v >> 57) & 7;
-}
-
-fn unpack15(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 15;
-    dst[1] = (v >> 4) & 15;
-    dst[2] = (v >> 8) & 15;
-    dst[3] = (v >> 12) & 15;
-    dst[4] = (v >> 16) & 15;
-    dst[5] = (v >> 20) & 15;
-    dst[6] = (v >> 24) & 15;
-    dst[7] = (v >> 28) & 15;
-    dst[8] = (v >> 32) & 15;
-    dst[9] = (v >> 36) & 15;
-    dst[10] = (v >> 40) & 15;
-    dst[11] = (v >> 44) & 15;
-    dst[12] = (v >> 48) & 15;
-    dst[13] = (v >> 52) & 15;
-    dst[14] = (v >> 56) & 15;
-}
-
-fn unpack12(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 31;
-    dst[1] = (v >> 5) & 31;
-    dst[2] = (v >> 10) & 31;
-    dst[3] = (v >> 15) & 31;
-    dst[4] = (v >> 20) & 31;
-    dst[5] = (v >> 25) & 31;
-    dst[6] = (v >> 30) & 31;
-    dst[7] = (v >> 35) & 31;
-    dst[8] = (v >> 40) & 31;
-    dst[9] = (v >> 45) & 31;
-    dst[10] = (v >> 50) & 31;
-    dst[11] = (v >> 55) & 31;
-}
-
-fn unpack10(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 63;
-    dst[1] = (v >> 6) & 63;
-    dst[2] = (v >> 12) & 63;
-    dst[3] = (v >> 18) & 63;
-    dst[4] = (v >> 24) & 63;
-    dst[5] = (v >> 30) & 63;
-    dst[6] = (v >> 36) & 63;
-    dst[7] = (v >> 42) & 63;
-    dst[8] = (v >> 48) & 63;
-    dst[9] = (v >> 54) & 63;
-}
-
-fn unpack8(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 127;
-    dst[1] = (v >> 7) & 127;
-    dst[2] = (v >> 14) & 127;
-    dst[3] = (v >> 21) & 127;
-    dst[4] = (v >> 28) & 127;
-    dst[5] = (v >> 35) & 127;
-    dst[6] = (v >> 42) & 127;
-    dst[7] = (v >> 49) & 127;
-}
-
-fn unpack7(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 255;
-    dst[1] = (v >> 8) & 255;
-    dst[2] = (v >> 16) & 255;
-    dst[3] = (v >> 24) & 255;
-    dst[4] = (v >> 32) & 255;
-    dst[5] = (v >> 40) & 255;
-    dst[6] = (v >> 48) & 255;
-}
-
-fn unpack6(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 1023;
-    dst[1] = (v >> 10) & 1023;
-    dst[2] = (v >> 20) & 1023;
-    dst[3] = (v >> 30) & 1023;
-    dst[4] = (v >> 40) & 1023;
-    dst[5] = (v >> 50) & 1023;
-}
-
-fn unpack5(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 4095;
-    dst[1] = (v >> 12) & 4095;
-    dst[2] = (v >> 24) & 4095;
-    dst[3] = (v >> 36) & 4095;
-    dst[4] = (v >> 48) & 4095;
-}
-
-fn unpack4(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 32767;
-    dst[1] = (v >> 15) & 32767;
-    dst[2] = (v >> 30) & 32767;
-    dst[3] = (v >> 45) & 32767;
-}
-
-fn unpack3(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 1048575;
-    dst[1] = (v >> 20) & 1048575;
-    dst[2] = (v >> 40) & 1048575;
-}
-
-fn unpack2(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 1073741823;
-    dst[1] = (v >> 30) & 1073741823;
-}
-
-fn unpack1(v: u64, dst: &mut [u64]) {
-    dst[0] = v & 1152921504606846975;
+            emit(pack_word(next[i], &src[i..], opts))?;
+            i += opts.table.entry(next[i]).0;
+        }
+    }
+    Ok(())
+}
+
+// Selectors 0 and 1 carry no payload bits.
+fn pack_run(_src: &[u64]) -> u64 {
+    0
+}
+
+pub(crate) fn unpack_run<const N: usize>(_v: u64, dst: &mut [u64]) {
+    dst[..N].fill(1);
+}
+
+// Packs the first `N` values of `src`, `BITS` bits each, into the low bits of
+// a word. Both bounds are constants, so the loop unrolls into the same shifts
+// a hand-written kernel would use.
+fn pack<const N: usize, const BITS: usize>(src: &[u64]) -> u64 {
+    let mut v = 0;
+    for (i, &x) in src[..N].iter().enumerate() {
+        v |= x << (i * BITS);
+    }
+    v
+}
+
+pub(crate) fn unpack<const N: usize, const BITS: usize>(v: u64, dst: &mut [u64]) {
+    let mask = (1u64 << BITS) - 1;
+    for (i, d) in dst[..N].iter_mut().enumerate() {
+        *d = (v >> (i * BITS)) & mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_all_round_trip() {
+        let src: Vec<u64> = (0..1000).map(|i| (i * 7919) % 5000).collect();
+        let encoded = encode_all(&src).unwrap();
+        assert!(encoded.len() < src.len());
+        assert_eq!(decode_all::<u64>(&encoded).unwrap(), src);
+    }
+
+    #[test]
+    fn test_packing_kernels() {
+        assert_eq!(
+            pack::<5, 12>(&[1, 2, 3, 4, 4095]),
+            1 | 2 << 12 | 3 << 24 | 4 << 36 | 4095 << 48
+        );
+        for (sel, p) in SELECTOR.iter().enumerate().filter(|(_, p)| p.bit > 0) {
+            let max = (1u64 << p.bit) - 1;
+            let src: Vec<u64> = (0..p.n as u64).map(|i| max - i % (max + 1)).collect();
+            let word = pack_word(sel, &src, &Options::default());
+            assert_eq!(word >> 60, sel as u64);
+            let mut dst = vec![0; p.n];
+            assert_eq!(decode(&mut dst, word, &Options::default()), p.n);
+            assert_eq!(dst, src);
+        }
+    }
+
+    #[test]
+    fn test_runs_of_ones() {
+        let mut src = vec![1u64; 240 + 120 + 10];
+        src.push(9);
+        let encoded = encode_all(&src).unwrap();
+        assert_eq!(encoded[0] >> 60, 0);
+        assert_eq!(encoded[1] >> 60, 1);
+        assert_eq!(decode_all::<u64>(&encoded).unwrap(), src);
+    }
+
+    #[test]
+    fn test_run_values() {
+        let opts = Options {
+            run_values: true,
+            ..Options::default()
+        };
+        let mut src = vec![42u64; 480];
+        src.extend(vec![MAX_VALUE; 120]);
+        let encoded = opts.encode_all(&src).unwrap();
+        assert_eq!(encoded.len(), 3);
+        assert_eq!(opts.decode_all::<u64>(&encoded).unwrap(), src);
+
+        let mut encoder = Encoder::with_options(opts);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        let decoded: Vec<u64> = Decoder::with_options(&bytes, opts).trusted().collect();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn test_escape_large_values() {
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let mut src: Vec<u64> = (0..500).collect();
+        src[10] = u64::MAX;
+        src[11] = MAX_VALUE + 1;
+        src[300] = 1 << 63;
+        src.extend(vec![1u64; 130]);
+
+        let encoded = opts.encode_all(&src).unwrap();
+        let marker = encoded.iter().position(|&v| v == ESCAPE << 60 | 2).unwrap();
+        assert_eq!(opts.decode_all::<u64>(&encoded).unwrap(), src);
+        assert_eq!(
+            opts.decode_all::<u64>(&encoded[..marker + 2]),
+            Err(Error::UnexpectedEnd)
+        );
+
+        let mut words = vec![0u64; encoded.len()];
+        assert_eq!(opts.encode_all_into(&src, &mut words), Ok(encoded.len()));
+        assert_eq!(
+            encode_all(&src).unwrap_err(),
+            Error::ValueTooLarge {
+                index: 10,
+                value: u64::MAX
+            }
+        );
+
+        let mut encoder = Encoder::with_options(opts);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        let decoder = Decoder::with_options(&bytes, opts);
+        assert_eq!(decoder.size_hint(), (src.len(), Some(src.len())));
+        let decoded: Vec<u64> = decoder.trusted().collect();
+        assert_eq!(decoded, src);
+
+        // The escape block is cut short.
+        let bytes = [(ESCAPE << 60 | 2).to_be_bytes(), 7u64.to_be_bytes()].concat();
+        let decoded: Vec<_> = Decoder::with_options(&bytes, opts).collect();
+        assert_eq!(decoded, [Ok(7), Err(Error::UnexpectedEnd)]);
+    }
+
+    #[test]
+    fn test_optimal_strategy() {
+        let optimal = Options {
+            strategy: EncodeStrategy::Optimal,
+            ..Options::default()
+        };
+        let mut src = vec![1u64; 61];
+        src[0] = 7;
+        // Greedy packs 20x3, 30x2, 10x6 and 1x60.
+        assert_eq!(encode_all(&src).unwrap().len(), 4);
+        let encoded = optimal.encode_all(&src).unwrap();
+        assert_eq!(encoded.len(), 2);
+        assert_eq!(decode_all::<u64>(&encoded).unwrap(), src);
+
+        let src: Vec<u64> = (0..5000u64).map(|i| (i * i * 2654435761) % 97).collect();
+        let greedy = encode_all(&src).unwrap();
+        let encoded = optimal.encode_all(&src).unwrap();
+        assert!(encoded.len() <= greedy.len());
+        assert_eq!(decode_all::<u64>(&encoded).unwrap(), src);
+
+        let opts = Options {
+            run_values: true,
+            escape: true,
+            ..optimal
+        };
+        let mut src = vec![5u64; 300];
+        src.extend([u64::MAX, 3, 1 << 61]);
+        let encoded = opts.encode_all(&src).unwrap();
+        assert_eq!(opts.decode_all::<u64>(&encoded).unwrap(), src);
+        assert_eq!(
+            optimal.encode_all(&src),
+            Err(Error::ValueTooLarge {
+                index: 300,
+                value: u64::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn test_encoder_streams_unbounded_input() {
+        let src: Vec<u64> = (0..10_000).map(|i| i % 300).collect();
+        let mut encoder = Encoder::new();
+        for &v in &src {
+            encoder.write(v).unwrap();
+        }
+        assert!(!encoder.bytes().is_empty());
+
+        let bytes = encoder.finish();
+        let words: Vec<u64> = bytes.chunks(8).map(BigEndian::read_u64).collect();
+        assert_eq!(words, encode_all(&src).unwrap());
+        assert_eq!(decode_all::<u64>(&words).unwrap(), src);
+    }
+
+    #[test]
+    fn test_decoder_iterator() {
+        let src: Vec<u64> = (0..1000).map(|i| i % 37).collect();
+        let mut encoder = Encoder::new();
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+
+        let decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.size_hint(), (src.len(), Some(src.len())));
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        let sum: u64 = Decoder::new(&bytes).trusted().sum();
+        assert_eq!(sum, src.iter().sum());
+    }
+
+    #[test]
+    fn test_decoder_skip() {
+        let src: Vec<u64> = crate::sample(5000);
+        let mut encoder = Encoder::new();
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.next(), Some(Ok(src[0])));
+        assert_eq!(decoder.skip_values(1000), Ok(1000));
+        assert_eq!(decoder.next(), Some(Ok(src[1001])));
+        assert_eq!(decoder.nth(2), Some(Ok(src[1004])));
+        assert_eq!(decoder.size_hint().0, src.len() - 1005);
+        assert_eq!(decoder.skip_values(10_000), Ok(src.len() - 1005));
+        assert_eq!(decoder.next(), None);
+        assert_eq!(Decoder::new(&bytes).trusted().nth(4321), Some(src[4321]));
+
+        // Raw words of an escape block are hopped without decoding.
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let src = [1, u64::MAX, u64::MAX - 1, u64::MAX - 2, 2];
+        let mut encoder = Encoder::with_options(opts);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        for (i, &v) in src.iter().enumerate() {
+            assert_eq!(Decoder::with_options(&bytes, opts).nth(i), Some(Ok(v)));
+        }
+        assert_eq!(
+            Decoder::with_options(&bytes[..24], opts).skip_values(4),
+            Err(Error::UnexpectedEnd)
+        );
+    }
+
+    fn custom_table() -> SelectorTable {
+        let mut entries = INFLUX_ENTRIES.map(|(n, bits)| (n as usize, bits as usize));
+        entries[10] = (6, 9);
+        entries[11] = (5, 11);
+        SelectorTable::new(200, entries, 0).unwrap()
+    }
+
+    #[test]
+    fn test_selector_tables() {
+        let mut src: Vec<u64> = crate::sample(1000);
+        src.extend_from_slice(&[0; 300]);
+        src.extend_from_slice(&[1 << 40, 500, 2000, 7]);
+
+        let zero_runs = Options {
+            table: SelectorTable::ZERO_RUNS,
+            ..Options::default()
+        };
+        let words = zero_runs.encode_all(&src).unwrap();
+        assert!(words.len() < encode_all(&src).unwrap().len());
+        assert_eq!(zero_runs.decode_all::<u64>(&words).unwrap(), src);
+
+        let custom = Options {
+            table: custom_table(),
+            ..Options::default()
+        };
+        for strategy in [EncodeStrategy::Greedy, EncodeStrategy::Optimal] {
+            let opts = Options { strategy, ..custom };
+            let words = opts.encode_all(&src).unwrap();
+            assert_eq!(opts.decode_all::<u64>(&words).unwrap(), src);
+        }
+        // Six 9-bit values take one word of selector 10.
+        let words = custom.encode_all(&[511u64, 1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(
+            words,
+            [10 << 60 | 5 << 45 | 4 << 36 | 3 << 27 | 2 << 18 | 1 << 9 | 511]
+        );
+
+        let mut encoder = Encoder::with_options(custom);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        let decoded: Result<Vec<u64>, Error> = Decoder::with_options(&bytes, custom).collect();
+        assert_eq!(decoded.unwrap(), src);
+    }
+
+    #[test]
+    fn test_selector_table_validation() {
+        let entries = INFLUX_ENTRIES.map(|(n, bits)| (n as usize, bits as usize));
+        assert!(SelectorTable::new(128, entries, 1).is_ok());
+        assert_eq!(SelectorTable::new(1, entries, 1), Err(Error::InvalidTable));
+        assert_eq!(
+            SelectorTable::new(128, entries, MAX_VALUE + 1),
+            Err(Error::InvalidTable)
+        );
+
+        let mut wide = entries;
+        wide[2] = (31, 2);
+        assert_eq!(SelectorTable::new(128, wide, 1), Err(Error::InvalidTable));
+
+        // Without a 60-bit entry large values could not be packed.
+        let mut narrow = entries;
+        narrow[15] = (2, 30);
+        assert_eq!(SelectorTable::new(128, narrow, 1), Err(Error::InvalidTable));
+
+        assert_eq!(SelectorTable::preset(1), Some(SelectorTable::ZERO_RUNS));
+        assert_eq!(SelectorTable::preset(128), None);
+    }
+
+    #[test]
+    fn test_tail_packing() {
+        let tail = Options {
+            tail: true,
+            ..Options::default()
+        };
+        // Greedy packing ends 30×2, 8×7, 1×60; the tail fits in one 10×6.
+        let src: Vec<u64> = (0..39).map(|i| i % 4).collect();
+        assert_eq!(encode_all(&src).unwrap().len(), 3);
+        let words = tail.encode_all(&src).unwrap();
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[1] >> 60, 7);
+        assert_eq!(tail.decode_all_n::<u64>(&words, src.len()).unwrap(), src);
+        assert_eq!(
+            tail.decode_all_n::<u64>(&words, 50),
+            Err(Error::CountMismatch {
+                expected: 50,
+                actual: 40
+            })
+        );
+
+        let mut decoder = WordsDecoder::with_options(&words, tail).limit(src.len());
+        let mut buf = [0; 64];
+        assert_eq!(decoder.decode_into(&mut buf), Ok(39));
+        assert_eq!(buf[..39], src);
+        assert_eq!(decoder.decode_into(&mut buf), Ok(0));
+        let mut decoder = WordsDecoder::with_options(&words, tail).limit(50);
+        assert_eq!(decoder.decode_into(&mut buf), Ok(40));
+        assert_eq!(
+            decoder.decode_into(&mut buf),
+            Err(Error::CountMismatch {
+                expected: 50,
+                actual: 40
+            })
+        );
+
+        let optimal = Options {
+            strategy: EncodeStrategy::Optimal,
+            ..tail
+        };
+        assert_eq!(optimal.encode_all(&src).unwrap(), words);
+
+        let mut encoder = Encoder::with_options(tail);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        assert_eq!(bytes.len(), 16);
+        let decoded: Result<Vec<u64>, Error> = Decoder::new(&bytes).limit(src.len()).collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        // A short run of ones takes a run word, padded with more ones.
+        let words = tail.encode_all(&[1u64; 100]).unwrap();
+        assert_eq!(words, [1 << 60]);
+        assert_eq!(tail.decode_all::<u64>(&words).unwrap().len(), 120);
+        assert_eq!(tail.decode_all_n::<u8>(&words, 100).unwrap(), [1; 100]);
+        assert_eq!(tail.encode_all::<u64>(&[]).unwrap(), []);
+    }
+
+    #[test]
+    fn test_decoder_limit() {
+        let src = [3, 1, 4, 1, 5, 9, 2];
+        let mut encoder = Encoder::new();
+        encoder.set_values(&src).unwrap();
+        assert_eq!(encoder.len(), 7);
+        let mut bytes = encoder.finish();
+
+        // Trailing padding, here a word of 60 zeros, is not part of the data.
+        bytes.extend_from_slice(&(2u64 << 60).to_be_bytes());
+        let decoder = Decoder::new(&bytes).limit(src.len());
+        assert_eq!(decoder.size_hint(), (7, Some(7)));
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src);
+
+        let mut decoder = Decoder::new(&bytes).limit(6);
+        assert_eq!(decoder.skip_values(10), Ok(6));
+        assert_eq!(decoder.next(), None);
+
+        // Without the padding word the input falls short of a limit of 10.
+        let decoded: Vec<_> = Decoder::new(&bytes[..8]).limit(10).collect();
+        let mismatch = Error::CountMismatch {
+            expected: 10,
+            actual: 7,
+        };
+        assert_eq!(decoded.len(), 8);
+        assert_eq!(decoded[7], Err(mismatch));
+        assert_eq!(Decoder::new(&bytes[..8]).limit(10).size_hint().0, 8);
+        assert_eq!(
+            Decoder::new(&bytes[..8]).limit(10).skip_values(20),
+            Err(mismatch)
+        );
+
+        // An error ends decoding, but `set_bytes` starts over with the limit.
+        let mut decoder = Decoder::new(&[]).limit(5);
+        assert_eq!(
+            decoder.next(),
+            Some(Err(Error::CountMismatch {
+                expected: 5,
+                actual: 0
+            }))
+        );
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.size_hint(), (0, Some(0)));
+        decoder.set_bytes(&bytes);
+        let decoded: Result<Vec<u64>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src[..5]);
+    }
+
+    #[test]
+    fn test_little_endian() {
+        let opts = Options {
+            endian: Endian::Little,
+            ..Options::default()
+        };
+        let src: Vec<u64> = (0..1000).map(|i| i % 37).collect();
+        let mut encoder = Encoder::with_options(opts);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+
+        let words = encode_all(&src).unwrap();
+        let native: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(bytes, native);
+        let decoded: Result<Vec<u64>, Error> = Decoder::with_options(&bytes, opts).collect();
+        assert_eq!(decoded.unwrap(), src);
+    }
+
+    #[test]
+    fn test_encoder_rejects_large_value() {
+        let mut encoder = Encoder::new();
+        encoder.write(1).unwrap();
+        assert_eq!(
+            encoder.write(u64::MAX),
+            Err(Error::ValueTooLarge {
+                index: 1,
+                value: u64::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn test_encode_all_short_input() {
+        let src = [3u64, 1 << 40, 7];
+        let encoded = encode_all(&src).unwrap();
+        assert_eq!(decode_all::<u64>(&encoded).unwrap(), src);
+        assert!(encode_all::<u64>(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_generic_integers() {
+        let ids: Vec<u32> = (0..1000).map(|i| i * 3).collect();
+        let encoded = encode_all::<u32>(&ids).unwrap();
+        assert_eq!(decode_all::<u32>(&encoded).unwrap(), ids);
+
+        let deltas: Vec<i16> = (0..1000).map(|i| (i % 11) - 5).collect();
+        let encoded = encode_all(&deltas).unwrap();
+        // Zigzag keeps -5..=5 within 4 bits.
+        assert_eq!(encoded.len(), deltas.len().div_ceil(15));
+        assert_eq!(decode_all::<i16>(&encoded).unwrap(), deltas);
+
+        let mut small = [0u8; 3];
+        let encoded = encode_all(&[1u64, 300, 2]).unwrap();
+        assert_eq!(
+            decode_all_into(&encoded, &mut small),
+            Err(Error::ValueTooLarge {
+                index: 1,
+                value: 300
+            })
+        );
+    }
+
+    // Windowed widening must pick the same words as encoding the `u64`s.
+    #[test]
+    fn test_generic_windows_match_u64() {
+        let mut src: Vec<i64> = (0..2000).map(|i| (i % 37) - 18).collect();
+        src[200..700].fill(0);
+        // An escape run straddling the first window boundary.
+        src[470..490].fill(i64::MIN);
+        src.truncate(1250);
+        let wide: Vec<u64> = src.iter().map(|v| v.to_u64()).collect();
+        for opts in [
+            Options {
+                escape: true,
+                ..Options::default()
+            },
+            Options {
+                escape: true,
+                tail: true,
+                table: SelectorTable::ZERO_RUNS,
+                ..Options::default()
+            },
+        ] {
+            let encoded = opts.encode_all(&src).unwrap();
+            assert_eq!(encoded, opts.encode_all(&wide).unwrap());
+            assert_eq!(opts.decode_all_n::<i64>(&encoded, src.len()).unwrap(), src);
+        }
+
+        let mut src = vec![1i64; 1000];
+        src[900] = i64::MIN;
+        let err = Error::ValueTooLarge {
+            index: 900,
+            value: u64::MAX,
+        };
+        assert_eq!(encode_all(&src), Err(err));
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let encoded = opts.encode_all(&src).unwrap();
+        let mut small = vec![0i32; 1000];
+        assert_eq!(opts.decode_all_into(&encoded, &mut small), Err(err));
+    }
+
+    // First fit over the packed selectors, densest first, checking every
+    // value against the selector's width. Shares no code with `select`.
+    fn select_reference(src: &[u64]) -> Option<usize> {
+        (2..SELECTOR.len()).find(|&sel| {
+            let p = &SELECTOR[sel];
+            src.len() >= p.n && src[..p.n].iter().all(|&v| v >> p.bit == 0)
+        })
+    }
+
+    #[test]
+    fn test_select_matches_reference() {
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        let mut src = [0u64; 64];
+        for bits in 0..64 {
+            for _ in 0..32 {
+                for v in src.iter_mut() {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    // Narrow some values so widths vary within a word.
+                    *v = x >> (63 - bits) >> 1 >> ((x & 3) * 8);
+                }
+                for len in [1, 7, 13, 59, 60, 64] {
+                    let src = &src[..len];
+                    assert_eq!(select(src), select_reference(src), "{:?}", src);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_all_value_too_large() {
+        let src = [1u64, 2, MAX_VALUE + 1];
+        assert_eq!(
+            encode_all(&src),
+            Err(Error::ValueTooLarge {
+                index: 2,
+                value: MAX_VALUE + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_decoder_truncated_input() {
+        let mut bytes = Encoder::new();
+        bytes.write(5).unwrap();
+        let mut bytes = bytes.finish();
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.size_hint(), (2, Some(2)));
+        assert_eq!(decoder.next(), Some(Ok(5)));
+        assert_eq!(
+            decoder.next(),
+            Some(Err(Error::TruncatedInput { remaining: 3 }))
+        );
+        assert_eq!(decoder.next(), None);
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = Error::TruncatedInput { remaining: 3 };
+        assert_eq!(err.to_string(), "input ends with a partial word of 3 bytes");
+    }
+
+    #[test]
+    fn test_words_decoder() {
+        let src: Vec<u64> = crate::sample(5000);
+        let words = encode_all(&src).unwrap();
+        let mut decoder = WordsDecoder::new(&words);
+        let mut decoded = Vec::new();
+        let mut buf = [0u64; BUF_SIZE];
+        loop {
+            let n = decoder.decode_into(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decoded, src);
+        assert!(decoder.is_empty());
+
+        // An escape block is split across calls that fill the buffer.
+        let opts = Options {
+            escape: true,
+            ..Options::default()
+        };
+        let src = [1, u64::MAX, u64::MAX - 1, u64::MAX - 2, 2];
+        let words = opts.encode_all(&src).unwrap();
+        let mut decoder = WordsDecoder::with_options(&words, opts);
+        let mut buf = [0u64; 2];
+        let mut decoded = Vec::new();
+        loop {
+            match decoder.decode_into(&mut buf).unwrap() {
+                0 => break,
+                n => decoded.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn test_words_decoder_from_bytes() {
+        let src: Vec<u64> = (0..1000).map(|i| i % 37).collect();
+        for endian in [Endian::Big, Endian::Little] {
+            let opts = Options {
+                endian,
+                ..Options::default()
+            };
+            let mut encoder = Encoder::with_options(opts);
+            encoder.set_values(&src).unwrap();
+            let bytes = encoder.finish();
+
+            // Copy into u64 storage so the view is aligned, as a mmap is.
+            let mut storage = vec![0u64; bytes.len() / 8 + 1];
+            let aligned = unsafe {
+                std::slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, storage.len() * 8)
+            };
+            aligned[..bytes.len()].copy_from_slice(&bytes);
+
+            let mut decoder = WordsDecoder::from_bytes(&aligned[..bytes.len()], opts).unwrap();
+            let mut decoded = vec![0u64; src.len()];
+            assert_eq!(decoder.decode_into(&mut decoded).unwrap(), src.len());
+            assert_eq!(decoded, src);
+
+            assert_eq!(
+                WordsDecoder::from_bytes(&aligned[1..9], opts).err(),
+                Some(Error::Misaligned)
+            );
+            assert_eq!(
+                WordsDecoder::from_bytes(&aligned[..12], opts).err(),
+                Some(Error::TruncatedInput { remaining: 4 })
+            );
+        }
+    }
+
+    #[test]
+    fn test_into_output_too_small() {
+        let src = [1u64 << 50, 1 << 50];
+        let mut words = [0u64; 1];
+        assert_eq!(
+            encode_all_into(&src, &mut words),
+            Err(Error::OutputTooSmall)
+        );
+
+        let encoded = encode_all(&src).unwrap();
+        let mut values = [0u64; 1];
+        assert_eq!(
+            decode_all_into(&encoded, &mut values),
+            Err(Error::OutputTooSmall)
+        );
+    }
 }
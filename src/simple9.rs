@@ -0,0 +1,153 @@
+//! Simple-9: 32-bit words with a 4-bit selector and nine equal-width
+//! packings of the 28 data bits. Selectors 9 to 15 are invalid.
+//!
+//! Suited to posting lists and other 32-bit columns; the API mirrors
+//! `simple8b` and reports the same `Error`.
+
+pub use crate::simple8b::{Endian, Error};
+pub use crate::word32::MAX_VALUE;
+use crate::word32::{self, Packing, Scheme};
+
+/// Selector table of Simple-9.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simple9;
+
+impl Scheme for Simple9 {
+    const TABLE: &'static [Packing] = &[
+        Packing::new(&[(28, 1)]),
+        Packing::new(&[(14, 2)]),
+        Packing::new(&[(9, 3)]),
+        Packing::new(&[(7, 4)]),
+        Packing::new(&[(5, 5)]),
+        Packing::new(&[(4, 7)]),
+        Packing::new(&[(3, 9)]),
+        Packing::new(&[(2, 14)]),
+        Packing::new(&[(1, 28)]),
+    ];
+}
+
+/// Streaming Simple-9 encoder.
+pub type Encoder = word32::Encoder<Simple9>;
+
+/// Decoder over a stream of Simple-9 words.
+pub type Decoder<'a> = word32::Decoder<'a, Simple9>;
+
+/// Iterator returned by `Decoder::trusted`.
+pub type TrustedDecoder<'a> = word32::TrustedDecoder<'a, Simple9>;
+
+/// Packs `src` into Simple-9 words. Every value must be at most `MAX_VALUE`.
+pub fn encode_all(src: &[u32]) -> Result<Vec<u32>, Error> {
+    word32::encode_all::<Simple9>(src)
+}
+
+/// Packs `src` into `dst` and returns the number of words written.
+pub fn encode_all_into(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    word32::encode_all_into::<Simple9>(src, dst)
+}
+
+/// Unpacks every word of `src`.
+pub fn decode_all(src: &[u32]) -> Result<Vec<u32>, Error> {
+    word32::decode_all::<Simple9>(src)
+}
+
+/// Unpacks every word of `src` into `dst` and returns the number of values
+/// written.
+pub fn decode_all_into(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    word32::decode_all_into::<Simple9>(src, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple9_round_trip() {
+        let src: Vec<u32> = crate::sample(10_000);
+        let words = encode_all(&src).unwrap();
+        assert_eq!(decode_all(&words).unwrap(), src);
+
+        let mut encoder = Encoder::new();
+        encoder.set_values(&src).unwrap();
+        assert_eq!(encoder.len(), src.len());
+        let bytes = encoder.finish();
+        assert_eq!(bytes.len(), words.len() * 4);
+        let decoded: Result<Vec<u32>, Error> = Decoder::new(&bytes).collect();
+        assert_eq!(decoded.unwrap(), src);
+    }
+
+    #[test]
+    fn test_simple9_packing() {
+        // 28 ones fill one word; 3×9 takes the three wider values after it.
+        let mut src = vec![1; 28];
+        src.extend_from_slice(&[300, 2, 511]);
+        let words = encode_all(&src).unwrap();
+        assert_eq!(words, [0x0fff_ffff, 6 << 28 | 511 << 18 | 2 << 9 | 300]);
+
+        assert_eq!(
+            encode_all(&[5, MAX_VALUE + 1]),
+            Err(Error::ValueTooLarge {
+                index: 1,
+                value: u64::from(MAX_VALUE) + 1,
+            })
+        );
+        assert_eq!(decode_all(&[9 << 28]), Err(Error::InvalidSelector(9)));
+    }
+
+    #[test]
+    fn test_simple9_decoder_errors() {
+        let mut bytes = (8u32 << 28 | 42).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[1, 2]);
+        let decoded: Vec<_> = Decoder::new(&bytes).collect();
+        assert_eq!(
+            decoded,
+            [Ok(42), Err(Error::TruncatedInput { remaining: 2 })]
+        );
+    }
+
+    #[test]
+    fn test_simple9_slices_and_decoder() {
+        let src: Vec<u32> = (0..1000).map(|i| i % 300).collect();
+        let words = encode_all(&src).unwrap();
+        let mut packed = vec![0; words.len()];
+        assert_eq!(encode_all_into(&src, &mut packed), Ok(words.len()));
+        assert_eq!(packed, words);
+        assert_eq!(
+            encode_all_into(&src, &mut packed[1..]),
+            Err(Error::OutputTooSmall)
+        );
+        let mut dst = vec![0; src.len()];
+        assert_eq!(decode_all_into(&words, &mut dst), Ok(src.len()));
+        assert_eq!(dst, src);
+        assert_eq!(
+            decode_all_into(&words, &mut dst[1..]),
+            Err(Error::OutputTooSmall)
+        );
+
+        let mut encoder = Encoder::with_endian(Endian::Little);
+        encoder.set_values(&src).unwrap();
+        let bytes = encoder.finish();
+        assert_eq!(bytes[..4], words[0].to_le_bytes());
+
+        let mut decoder = Decoder::with_endian(&bytes, Endian::Little);
+        assert_eq!(decoder.size_hint(), (1000, Some(1000)));
+        assert_eq!(decoder.skip_values(500), Ok(500));
+        assert_eq!(decoder.nth(10), Some(Ok(src[510])));
+        assert_eq!(decoder.size_hint(), (489, Some(489)));
+        let rest: Vec<u32> = decoder.trusted().collect();
+        assert_eq!(rest, src[511..]);
+
+        // A limit past the input ends in an error, after which `set_bytes`
+        // starts over with the same limit.
+        let mut decoder = Decoder::with_endian(&bytes[..8], Endian::Little).limit(100);
+        let mismatch = decoder.find(|r| r.is_err());
+        assert!(matches!(
+            mismatch,
+            Some(Err(Error::CountMismatch { expected: 100, .. }))
+        ));
+        assert_eq!(decoder.next(), None);
+        decoder.set_bytes(&bytes);
+        assert_eq!(decoder.size_hint(), (100, Some(100)));
+        let decoded: Result<Vec<u32>, Error> = decoder.collect();
+        assert_eq!(decoded.unwrap(), src[..100]);
+    }
+}
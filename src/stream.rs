@@ -0,0 +1,223 @@
+//! Limit, skip and error handling shared by the streaming decoders.
+//!
+//! Each codec supplies a `Source` that unpacks or hops over one block of its
+//! input at a time; `Stream` turns it into an iterator of values that honours
+//! a limit and stops after the first error.
+
+use crate::simple8b::Error;
+
+/// Packed input read one block at a time.
+pub(crate) trait Source {
+    type Value: Copy + Default;
+
+    /// Unpacks the next block into `buf` and returns its number of values,
+    /// which is 0 at the end of input.
+    fn read(&mut self, buf: &mut [Self::Value]) -> Result<usize, Error>;
+
+    /// Drops the next block without unpacking it if it holds at most `max`
+    /// values, and returns how many it held. Returns `None` if the block has
+    /// to be read instead, including when it is corrupt.
+    fn hop(&mut self, max: usize) -> Option<usize>;
+
+    /// Counts the values left, and whether the input ends in an error.
+    fn remaining(&self) -> (usize, bool);
+
+    /// Drops all remaining input.
+    fn clear(&mut self);
+}
+
+/// Iterator over the values of a `Source`, buffering one block of up to `N`.
+#[derive(Clone)]
+pub(crate) struct Stream<S: Source, const N: usize> {
+    pub(crate) src: S,
+    buf: [S::Value; N],
+    i: usize,
+    n: usize,
+    // Values to yield in total, if known, and values yielded so far.
+    limit: Option<usize>,
+    taken: usize,
+    // Set once an error has been yielded, until the next `restart`.
+    done: bool,
+}
+
+impl<S: Source, const N: usize> Stream<S, N> {
+    pub(crate) fn new(src: S) -> Self {
+        Stream {
+            src,
+            buf: [S::Value::default(); N],
+            i: 0,
+            n: 0,
+            limit: None,
+            taken: 0,
+            done: false,
+        }
+    }
+
+    pub(crate) fn limit(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Starts over after `src` has been given new input, keeping any limit.
+    pub(crate) fn restart(&mut self) {
+        self.i = 0;
+        self.n = 0;
+        self.taken = 0;
+        self.done = false;
+    }
+
+    pub(crate) fn skip_values(&mut self, n: usize) -> Result<usize, Error> {
+        if self.done {
+            return Ok(0);
+        }
+        let want = match self.limit {
+            Some(limit) => n.min(limit - self.taken),
+            None => n,
+        };
+        let mut r = self.hop(want);
+        if let Ok(k) = r {
+            self.taken += k;
+            if k < want {
+                r = self.mismatch().map_or(r, Err);
+            }
+        }
+        if r.is_err() {
+            self.fuse();
+        }
+        r
+    }
+
+    fn hop(&mut self, n: usize) -> Result<usize, Error> {
+        let mut left = n;
+        while left > 0 {
+            if self.i < self.n {
+                let k = left.min(self.n - self.i);
+                self.i += k;
+                left -= k;
+                continue;
+            }
+            if let Some(k) = self.src.hop(left) {
+                left -= k;
+                continue;
+            }
+            // The target is inside the next block, or the input ends here.
+            self.fill()?;
+            if self.n == 0 {
+                break;
+            }
+        }
+        Ok(n - left)
+    }
+
+    fn fill(&mut self) -> Result<(), Error> {
+        self.i = 0;
+        self.n = 0;
+        self.n = self.src.read(&mut self.buf)?;
+        Ok(())
+    }
+
+    fn fuse(&mut self) {
+        self.src.clear();
+        self.i = 0;
+        self.n = 0;
+        self.done = true;
+    }
+
+    // The error for input that ends before the limit, if there is one.
+    fn mismatch(&self) -> Option<Error> {
+        Some(Error::CountMismatch {
+            expected: self.limit? as u64,
+            actual: self.taken as u64,
+        })
+    }
+}
+
+impl<S: Source, const N: usize> Iterator for Stream<S, N> {
+    type Item = Result<S::Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.limit == Some(self.taken) {
+            return None;
+        }
+        if self.i >= self.n {
+            if let Err(e) = self.fill() {
+                self.fuse();
+                return Some(Err(e));
+            }
+            if self.n == 0 {
+                let e = self.mismatch()?;
+                self.fuse();
+                return Some(Err(e));
+            }
+        }
+        let v = self.buf[self.i];
+        self.i += 1;
+        self.taken += 1;
+        Some(Ok(v))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        match self.skip_values(n) {
+            Ok(k) if k == n => self.next(),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            return (0, Some(0));
+        }
+        let (values, failed) = self.src.remaining();
+        let values = values + self.n - self.i;
+        let n = match self.limit {
+            Some(limit) => {
+                let left = limit - self.taken;
+                // Falling short of the limit ends with an error item.
+                if values >= left {
+                    left
+                } else {
+                    values + 1
+                }
+            }
+            None => values + failed as usize,
+        };
+        (n, Some(n))
+    }
+}
+
+/// Iterator returned by a decoder's `trusted`.
+///
+/// # Panics
+///
+/// Panics if the input turns out to be corrupt.
+#[derive(Clone)]
+pub struct TrustedDecoder<I> {
+    inner: I,
+}
+
+impl<I> TrustedDecoder<I> {
+    pub(crate) fn new(inner: I) -> Self {
+        TrustedDecoder { inner }
+    }
+}
+
+impl<T, I: Iterator<Item = Result<T, Error>>> Iterator for TrustedDecoder<I> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner
+            .next()
+            .map(|r| r.unwrap_or_else(|e| panic!("corrupt input: {}", e)))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<T> {
+        self.inner
+            .nth(n)
+            .map(|r| r.unwrap_or_else(|e| panic!("corrupt input: {}", e)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
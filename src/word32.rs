@@ -0,0 +1,371 @@
+//! Engine shared by the 32-bit codecs, `simple9` and `simple16`.
+//!
+//! A word holds a 4-bit selector in its top bits and 28 data bits below it.
+//! Each scheme supplies a table mapping selectors to slot layouts; everything
+//! else, from greedy packing to the streaming types, lives here.
+
+use std::marker::PhantomData;
+
+use crate::simple8b::{Endian, Error};
+use crate::stream::{self, Source, Stream};
+
+/// Largest value a 32-bit word can hold.
+pub const MAX_VALUE: u32 = (1 << 28) - 1;
+
+// Most values any packing holds, the 28×1 layout.
+const MAX_N: usize = 28;
+
+/// Slot layout of one selector.
+pub struct Packing {
+    // Runs of (count, bits), filled from the low bits up.
+    groups: &'static [(usize, u32)],
+}
+
+impl Packing {
+    pub(crate) const fn new(groups: &'static [(usize, u32)]) -> Self {
+        Packing { groups }
+    }
+
+    fn n(&self) -> usize {
+        self.groups.iter().map(|&(n, _)| n).sum()
+    }
+
+    fn slots(&self) -> impl Iterator<Item = u32> + '_ {
+        self.groups
+            .iter()
+            .flat_map(|&(n, bits)| std::iter::repeat_n(bits, n))
+    }
+
+    fn fits(&self, src: &[u32]) -> bool {
+        src.len() >= self.n() && self.slots().zip(src).all(|(bits, &v)| v >> bits == 0)
+    }
+
+    fn pack(&self, src: &[u32]) -> u32 {
+        let mut word = 0;
+        let mut shift = 0;
+        for (bits, &v) in self.slots().zip(src) {
+            word |= v << shift;
+            shift += bits;
+        }
+        word
+    }
+
+    fn unpack(&self, word: u32, dst: &mut [u32]) -> usize {
+        let mut shift = 0;
+        let mut n = 0;
+        for (bits, d) in self.slots().zip(dst) {
+            *d = (word >> shift) & ((1 << bits) - 1);
+            shift += bits;
+            n += 1;
+        }
+        n
+    }
+}
+
+/// A selector table. Implemented by the marker types of `simple9` and
+/// `simple16`.
+pub trait Scheme {
+    /// Packings by selector, densest first. Selectors past the end are
+    /// invalid.
+    const TABLE: &'static [Packing];
+}
+
+// Packs as many leading values of `src` as fit in one word; `src` must not be
+// empty.
+fn encode<S: Scheme>(src: &[u32]) -> Result<(u32, usize), Error> {
+    for (sel, p) in S::TABLE.iter().enumerate() {
+        if p.fits(src) {
+            return Ok(((sel as u32) << 28 | p.pack(src), p.n()));
+        }
+    }
+    Err(Error::ValueTooLarge {
+        index: 0,
+        value: u64::from(src[0]),
+    })
+}
+
+fn packing<S: Scheme>(v: u32) -> Result<&'static Packing, Error> {
+    let sel = (v >> 28) as usize;
+    S::TABLE.get(sel).ok_or(Error::InvalidSelector(sel as u8))
+}
+
+// Unpacks `v` into `dst` and returns the number of values.
+fn decode<S: Scheme>(dst: &mut [u32], v: u32) -> Result<usize, Error> {
+    Ok(packing::<S>(v)?.unpack(v, dst))
+}
+
+pub(crate) fn encode_all<S: Scheme>(src: &[u32]) -> Result<Vec<u32>, Error> {
+    let mut dst = Vec::new();
+    let mut i = 0;
+    while i < src.len() {
+        let (v, n) = encode::<S>(&src[i..]).map_err(|e| e.at(i))?;
+        dst.push(v);
+        i += n;
+    }
+    Ok(dst)
+}
+
+pub(crate) fn encode_all_into<S: Scheme>(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    let mut i = 0;
+    let mut j = 0;
+    while i < src.len() {
+        let (v, n) = encode::<S>(&src[i..]).map_err(|e| e.at(i))?;
+        *dst.get_mut(j).ok_or(Error::OutputTooSmall)? = v;
+        i += n;
+        j += 1;
+    }
+    Ok(j)
+}
+
+pub(crate) fn decode_all<S: Scheme>(src: &[u32]) -> Result<Vec<u32>, Error> {
+    let mut dst = Vec::with_capacity(src.len());
+    let mut buf = [0; MAX_N];
+    for &v in src {
+        let n = decode::<S>(&mut buf, v)?;
+        dst.extend_from_slice(&buf[..n]);
+    }
+    Ok(dst)
+}
+
+pub(crate) fn decode_all_into<S: Scheme>(src: &[u32], dst: &mut [u32]) -> Result<usize, Error> {
+    let mut j = 0;
+    for &v in src {
+        if j + packing::<S>(v)?.n() > dst.len() {
+            return Err(Error::OutputTooSmall);
+        }
+        j += decode::<S>(&mut dst[j..], v)?;
+    }
+    Ok(j)
+}
+
+/// Streaming encoder writing 32-bit words, big-endian unless set otherwise.
+///
+/// Values are buffered until a full window is available, then packed
+/// greedily. `finish` packs whatever is still buffered.
+#[derive(Clone)]
+pub struct Encoder<S> {
+    buf: [u32; MAX_N],
+    t: usize,
+    n: usize,
+    bytes: Vec<u8>,
+    endian: Endian,
+    scheme: PhantomData<S>,
+}
+
+impl<S: Scheme> Encoder<S> {
+    pub fn new() -> Self {
+        Self::with_endian(Endian::Big)
+    }
+
+    /// Writes words in `endian` byte order.
+    pub fn with_endian(endian: Endian) -> Self {
+        Encoder {
+            buf: [0; MAX_N],
+            t: 0,
+            n: 0,
+            bytes: Vec::new(),
+            endian,
+            scheme: PhantomData,
+        }
+    }
+
+    /// Resets the encoder and writes every value of `v`.
+    pub fn set_values(&mut self, v: &[u32]) -> Result<(), Error> {
+        self.reset();
+        for &x in v {
+            self.write(x)?;
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.t = 0;
+        self.n = 0;
+        self.bytes.clear();
+    }
+
+    /// Buffers `v`, packing a word as soon as a full window is buffered.
+    pub fn write(&mut self, v: u32) -> Result<(), Error> {
+        if v > MAX_VALUE {
+            return Err(Error::ValueTooLarge {
+                index: self.n,
+                value: u64::from(v),
+            });
+        }
+        self.buf[self.t] = v;
+        self.t += 1;
+        self.n += 1;
+        if self.t == MAX_N {
+            self.flush();
+        }
+        Ok(())
+    }
+
+    /// Packs one word from the buffered values, if there are any.
+    pub fn flush(&mut self) {
+        if self.t == 0 {
+            return;
+        }
+        // Values are range checked on write, so packing cannot fail here.
+        let (v, n) = encode::<S>(&self.buf[..self.t]).expect("buffered values are valid");
+        self.bytes.extend_from_slice(&self.endian.bytes32(v));
+        self.buf.copy_within(n..self.t, 0);
+        self.t -= n;
+    }
+
+    /// Packs all buffered values and returns the encoded bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        while self.t > 0 {
+            self.flush();
+        }
+        self.bytes
+    }
+
+    /// Returns the number of values written since the last reset.
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Returns the words packed so far.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<S: Scheme> Default for Encoder<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decoder over a stream of 32-bit words, big-endian unless set otherwise.
+///
+/// Yields one `Result` per value. Decoding stops after the first error.
+#[derive(Clone)]
+pub struct Decoder<'a, S: Scheme> {
+    inner: Stream<Input<'a, S>, MAX_N>,
+}
+
+/// Iterator returned by `Decoder::trusted`.
+pub type TrustedDecoder<'a, S> = stream::TrustedDecoder<Decoder<'a, S>>;
+
+impl<'a, S: Scheme> Decoder<'a, S> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_endian(bytes, Endian::Big)
+    }
+
+    /// Reads words in `endian` byte order.
+    pub fn with_endian(bytes: &'a [u8], endian: Endian) -> Self {
+        Decoder {
+            inner: Stream::new(Input {
+                bytes,
+                endian,
+                scheme: PhantomData,
+            }),
+        }
+    }
+
+    /// Stops after exactly `count` values, the number `Encoder::len` reported.
+    ///
+    /// Anything packed past them is never yielded, and input that ends
+    /// before them fails with `CountMismatch`.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.inner = self.inner.limit(count);
+        self
+    }
+
+    /// Restarts decoding over `bytes`, keeping any limit.
+    pub fn set_bytes(&mut self, bytes: &'a [u8]) {
+        self.inner.src.bytes = bytes;
+        self.inner.restart();
+    }
+
+    /// Converts into an iterator of plain values for input known to be valid.
+    pub fn trusted(self) -> TrustedDecoder<'a, S> {
+        TrustedDecoder::new(self)
+    }
+
+    /// Skips up to `n` values and returns how many were skipped, which is
+    /// less than `n` only at the end of input.
+    ///
+    /// Whole words are hopped over by their selector; only the word holding
+    /// the next value to yield is unpacked. Decoding stops after an error,
+    /// as with `next`.
+    pub fn skip_values(&mut self, n: usize) -> Result<usize, Error> {
+        self.inner.skip_values(n)
+    }
+}
+
+impl<S: Scheme> Iterator for Decoder<'_, S> {
+    type Item = Result<u32, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+// Input of a `Decoder`.
+#[derive(Clone)]
+struct Input<'a, S> {
+    bytes: &'a [u8],
+    endian: Endian,
+    scheme: PhantomData<S>,
+}
+
+impl<S: Scheme> Source for Input<'_, S> {
+    type Value = u32;
+
+    fn read(&mut self, buf: &mut [u32]) -> Result<usize, Error> {
+        if self.bytes.is_empty() {
+            return Ok(0);
+        }
+        if self.bytes.len() < 4 {
+            return Err(Error::TruncatedInput {
+                remaining: self.bytes.len(),
+            });
+        }
+        let v = self.endian.read32(self.bytes);
+        self.bytes = &self.bytes[4..];
+        decode::<S>(buf, v)
+    }
+
+    fn hop(&mut self, max: usize) -> Option<usize> {
+        if self.bytes.len() < 4 {
+            return None;
+        }
+        let c = packing::<S>(self.endian.read32(self.bytes)).ok()?.n();
+        if c > max {
+            return None;
+        }
+        self.bytes = &self.bytes[4..];
+        Some(c)
+    }
+
+    fn remaining(&self) -> (usize, bool) {
+        let mut n = 0;
+        let mut words = self.bytes.chunks_exact(4);
+        for w in words.by_ref() {
+            match packing::<S>(self.endian.read32(w)) {
+                Ok(p) => n += p.n(),
+                Err(_) => return (n, true),
+            }
+        }
+        (n, !words.remainder().is_empty())
+    }
+
+    fn clear(&mut self) {
+        self.bytes = &[];
+    }
+}